@@ -27,6 +27,9 @@ pub struct lua_State;
 
 pub type lua_CFunction = extern "C" fn(L: *mut lua_State) -> c_int;
 
+pub type lua_KContext = libc::intptr_t;
+pub type lua_KFunction = extern "C" fn(L: *mut lua_State, status: c_int, ctx: lua_KContext) -> c_int;
+
 pub type lua_Reader = extern "C" fn(L: *mut lua_State,
                                     ud: *mut libc::c_void,
                                     sz: *mut libc::size_t)
@@ -58,10 +61,13 @@ pub const LUA_TFUNCTION: c_int = 6;
 pub const LUA_TUSERDATA: c_int = 7;
 pub const LUA_TTHREAD: c_int = 8;
 
+pub const LUA_NOREF: c_int = -2;
+pub const LUA_REFNIL: c_int = -1;
+
 pub const LUA_MINSTACK: c_int = 20;
 
-pub const LUA_RIDX_MAINTHREAD: c_int = 1;
-pub const LUA_RIDX_GLOBALS: c_int = 2;
+pub const LUA_RIDX_MAINTHREAD: lua_Integer = 1;
+pub const LUA_RIDX_GLOBALS: lua_Integer = 2;
 
 pub type lua_Number = libc::c_double;
 pub type lua_Integer = libc::ptrdiff_t;
@@ -118,7 +124,8 @@ pub struct lua_Debug {
     pub nparams: libc::c_uchar,
     pub isvararg: libc::c_char,
     pub istailcall: libc::c_char,
-    pub short_src: [libc::c_char; 60], // i_ci: *CallInfo
+    pub short_src: [libc::c_char; 60],
+    pub i_ci: *mut libc::c_void, // CallInfo of the active function
 }
 
 extern "C" {
@@ -143,6 +150,7 @@ extern "C" {
     pub fn lua_xmove(from: *mut lua_State, to: *mut lua_State, n: c_int);
 
     pub fn lua_isnumber(L: *mut lua_State, idx: c_int) -> c_int;
+    pub fn lua_isinteger(L: *mut lua_State, idx: c_int) -> c_int;
     pub fn lua_isstring(L: *mut lua_State, idx: c_int) -> c_int;
     pub fn lua_iscfunction(L: *mut lua_State, idx: c_int) -> c_int;
     pub fn lua_isuserdata(L: *mut lua_State, idx: c_int) -> c_int;
@@ -186,7 +194,7 @@ extern "C" {
     pub fn lua_gettable(L: *mut lua_State, idx: c_int);
     pub fn lua_getfield(L: *mut lua_State, idx: c_int, k: *const libc::c_char);
     pub fn lua_rawget(L: *mut lua_State, idx: c_int);
-    pub fn lua_rawgeti(L: *mut lua_State, idx: c_int, n: c_int);
+    pub fn lua_rawgeti(L: *mut lua_State, idx: c_int, n: lua_Integer);
     pub fn lua_rawgetp(L: *mut lua_State, idx: c_int, p: *const libc::c_char);
     pub fn lua_createtable(L: *mut lua_State, narr: c_int, nrec: c_int);
     pub fn lua_newuserdata(L: *mut lua_State, sz: libc::size_t) -> *mut libc::c_void;
@@ -197,7 +205,7 @@ extern "C" {
     pub fn lua_settable(L: *mut lua_State, idx: c_int);
     pub fn lua_setfield(L: *mut lua_State, idx: c_int, k: *const libc::c_char);
     pub fn lua_rawset(L: *mut lua_State, idx: c_int);
-    pub fn lua_rawseti(L: *mut lua_State, idx: c_int, n: c_int);
+    pub fn lua_rawseti(L: *mut lua_State, idx: c_int, n: lua_Integer);
     pub fn lua_rawsetp(L: *mut lua_State, idx: c_int, p: *const libc::c_char);
     pub fn lua_setmetatable(L: *mut lua_State, objindex: c_int) -> c_int;
     pub fn lua_setfenv(L: *mut lua_State, idx: c_int) -> c_int;
@@ -205,15 +213,14 @@ extern "C" {
     pub fn lua_callk(L: *mut lua_State,
                      nargs: c_int,
                      nresults: c_int,
-                     ctx: c_int,
-                     k: Option<lua_CFunction>);
-    pub fn lua_getctx(L: *mut lua_State, ctx: c_int) -> c_int;
+                     ctx: lua_KContext,
+                     k: Option<lua_KFunction>);
     pub fn lua_pcallk(L: *mut lua_State,
                       nargs: c_int,
                       nresults: c_int,
                       errfunc: c_int,
-                      ctx: c_int,
-                      k: Option<lua_CFunction>)
+                      ctx: lua_KContext,
+                      k: Option<lua_KFunction>)
                       -> c_int;
     pub fn lua_load(L: *mut lua_State,
                     reader: lua_Reader,
@@ -225,11 +232,12 @@ extern "C" {
 
     pub fn lua_yieldk(L: *mut lua_State,
                       nresults: c_int,
-                      ctx: c_int,
-                      k: Option<lua_CFunction>)
+                      ctx: lua_KContext,
+                      k: Option<lua_KFunction>)
                       -> c_int;
     pub fn lua_resume(L: *mut lua_State, from: *mut lua_State, narg: c_int) -> c_int;
     pub fn lua_status(L: *mut lua_State) -> c_int;
+    pub fn lua_isyieldable(L: *mut lua_State) -> c_int;
 
     pub fn lua_gc(L: *mut lua_State, what: c_int, data: c_int) -> c_int;
 
@@ -257,10 +265,17 @@ extern "C" {
     pub fn lua_gethookcount(L: *mut lua_State) -> c_int;
 
     pub fn luaL_openlibs(L: *mut lua_State);
+    pub fn luaL_requiref(L: *mut lua_State, modname: *const libc::c_char, openf: lua_CFunction, glb: c_int);
     pub fn luaL_newstate() -> *mut lua_State;
     pub fn luaL_loadstring(L: *mut lua_State, p: *const libc::c_char) -> c_int;
     pub fn luaL_setmetatable(L: *mut lua_State, tname: *const libc::c_char);
     pub fn luaL_error(L: *mut lua_State, info: *const libc::c_char);
+    pub fn luaL_ref(L: *mut lua_State, t: c_int) -> c_int;
+    pub fn luaL_unref(L: *mut lua_State, t: c_int, r: c_int);
+    pub fn luaL_traceback(L: *mut lua_State,
+                          L1: *mut lua_State,
+                          msg: *const libc::c_char,
+                          level: c_int);
     pub fn luaL_loadbufferx(L: *mut lua_State,
                             buff: *const libc::c_char,
                             sz: libc::size_t,
@@ -268,6 +283,17 @@ extern "C" {
                             mode: *const libc::c_char)
                             -> c_int;
 
+    pub fn luaopen_base(L: *mut lua_State) -> c_int;
+    pub fn luaopen_coroutine(L: *mut lua_State) -> c_int;
+    pub fn luaopen_table(L: *mut lua_State) -> c_int;
+    pub fn luaopen_io(L: *mut lua_State) -> c_int;
+    pub fn luaopen_os(L: *mut lua_State) -> c_int;
+    pub fn luaopen_string(L: *mut lua_State) -> c_int;
+    pub fn luaopen_utf8(L: *mut lua_State) -> c_int;
+    pub fn luaopen_bit32(L: *mut lua_State) -> c_int;
+    pub fn luaopen_math(L: *mut lua_State) -> c_int;
+    pub fn luaopen_debug(L: *mut lua_State) -> c_int;
+    pub fn luaopen_package(L: *mut lua_State) -> c_int;
 }
 #[inline(always)]
 pub fn lua_upvalueindex(i: c_int) -> c_int {
@@ -395,6 +421,7 @@ impl default::Default for lua_Debug {
             isvararg: 0,
             istailcall: 0,
             short_src: [0; 60],
+            i_ci: ptr::null_mut(),
         }
     }
 }
@@ -0,0 +1,105 @@
+extern crate td_rlua;
+
+use std::any::TypeId;
+use std::time::Instant;
+
+use td_rlua::lua_State;
+use td_rlua::Lua;
+use td_rlua::LuaPush;
+use td_rlua::LuaRead;
+
+struct Entity {
+    hp: i32,
+}
+
+impl LuaPush for Entity {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        // the name of the type is kept for `read_by_type_name`
+        td_rlua::userdata::push_userdata(self, lua, |mut metatable| {
+            metatable.set("__typeid", format!("{:?}", TypeId::of::<Entity>()));
+        })
+    }
+}
+
+impl<'a> LuaRead for &'a mut Entity {
+    fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut Entity> {
+        td_rlua::userdata::read_userdata(lua, index)
+    }
+}
+
+// the check made by `read_userdata` before the metatables were cached, comparing the name of
+// the type formatted at each read with the `__typeid` field of the metatable, as a baseline
+fn read_by_type_name<'a, T: 'static>(lua: *mut lua_State, index: i32) -> Option<&'a mut T> {
+    unsafe {
+        let expected_typeid = format!("{:?}", TypeId::of::<T>());
+        let data_ptr = td_rlua::lua_touserdata(lua, index);
+        if data_ptr.is_null() {
+            return None;
+        }
+        if td_rlua::lua_getmetatable(lua, index) == 0 {
+            return None;
+        }
+
+        "__typeid".push_to_lua(lua);
+        td_rlua::lua_gettable(lua, -2);
+        match <String as LuaRead>::lua_read(lua) {
+            Some(ref val) if val == &expected_typeid => {}
+            _ => {
+                td_rlua::lua_pop(lua, 2);
+                return None;
+            }
+        }
+        td_rlua::lua_pop(lua, 2);
+        Some(&mut *(data_ptr as *mut T))
+    }
+}
+
+fn bench<F: FnMut()>(name: &str, iterations: u32, mut f: F) {
+    let start = Instant::now();
+    f();
+    let elapsed = start.elapsed();
+    let nanos = elapsed.as_secs() as f64 * 1e9 + elapsed.subsec_nanos() as f64;
+    println!("{:<10} {:>10} iterations {:>10.1} ns/iter", name, iterations, nanos / iterations as f64);
+}
+
+fn main() {
+    const PUSHES: u32 = 200_000;
+    const READS: u32 = 1_000_000;
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    bench("push", PUSHES, || {
+        for i in 0..PUSHES {
+            lua.set("e", Entity { hp: i as i32 });
+        }
+    });
+
+    lua.set("e", Entity { hp: 1 });
+    let state = lua.state();
+    unsafe { td_rlua::lua_getglobal(state, b"e\0".as_ptr() as *const _) };
+    bench("read", READS, || {
+        let mut total = 0;
+        for _ in 0..READS {
+            let entity: &mut Entity = td_rlua::userdata::read_userdata(state, -1).unwrap();
+            total += entity.hp;
+        }
+        assert_eq!(total, READS as i32);
+    });
+    bench("read name", READS, || {
+        let mut total = 0;
+        for _ in 0..READS {
+            let entity: &mut Entity = read_by_type_name(state, -1).unwrap();
+            total += entity.hp;
+        }
+        assert_eq!(total, READS as i32);
+    });
+    unsafe { td_rlua::lua_settop(state, 0) };
+
+    lua.set("hp", td_rlua::function1(|entity: &mut Entity| entity.hp));
+    let script = format!("local n = 0 for i = 1, {} do n = n + hp(e) end return n", READS);
+    bench("call", READS, || {
+        let total: i32 = lua.exec_string(script.as_str()).unwrap();
+        assert_eq!(total, READS as i32);
+    });
+}
@@ -0,0 +1,298 @@
+use std::error::Error;
+use std::ffi::{CStr, CString};
+use std::fmt;
+use std::mem;
+use std::ptr;
+
+use libc;
+use td_clua::{self, lua_State};
+
+use limits::Exceeded;
+use panic;
+use state;
+
+/// Errors that can happen while executing Lua code or reading its results.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaError {
+    /// The chunk could not be compiled.
+    Syntax(String),
+    /// The code raised an error while running.
+    Runtime {
+        message: String,
+        traceback: Option<String>,
+        frames: Vec<StackFrame>,
+    },
+    /// Lua failed to allocate memory.
+    Memory(String),
+    /// The code ran longer than the timeout set with `Lua::set_timeout`.
+    Timeout,
+    /// The code executed more instructions than allowed by `Lua::set_instruction_limit`.
+    InstructionLimit,
+    /// The error handler itself failed while handling an error.
+    ErrorHandler(String),
+    /// The value returned by Lua could not be converted to the requested Rust type.
+    Conversion {
+        expected: String,
+        actual: String,
+    },
+    /// A value could not be serialized to or deserialized from Lua.
+    #[cfg(feature = "serde")]
+    Serde(String),
+}
+
+pub type LuaResult<T> = Result<T, LuaError>;
+
+/// One level of the Lua call stack at the moment an error was raised.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StackFrame {
+    /// Printable name of the chunk the function was defined in.
+    pub source: String,
+    /// Line being executed, `None` when not available (C functions).
+    pub line: Option<u32>,
+    /// Name the function was called with, if Lua can find one.
+    pub name: Option<String>,
+    /// How the name was found: `"global"`, `"local"`, `"method"`, `"field"`, `"upvalue"` or `""`.
+    pub namewhat: String,
+    /// `"Lua"`, `"C"` or `"main"`.
+    pub what: String,
+    /// Names and printable values of the active local variables,
+    /// only filled when enabled with `Lua::set_capture_locals`.
+    pub locals: Vec<(String, String)>,
+}
+
+impl LuaError {
+    /// Returns the `lua_pcall` status code corresponding to this error.
+    pub fn status(&self) -> i32 {
+        match *self {
+            LuaError::Syntax(_) => td_clua::LUA_ERRSYNTAX,
+            LuaError::Runtime { .. } => td_clua::LUA_ERRRUN,
+            LuaError::Memory(_) => td_clua::LUA_ERRMEM,
+            LuaError::Timeout | LuaError::InstructionLimit => td_clua::LUA_ERRRUN,
+            LuaError::ErrorHandler(_) => td_clua::LUA_ERRERR,
+            LuaError::Conversion { .. } => td_clua::LUA_ERRRUN,
+            #[cfg(feature = "serde")]
+            LuaError::Serde(_) => td_clua::LUA_ERRRUN,
+        }
+    }
+
+    /// Builds the error matching a failed `lua_pcall` or `lua_load` status,
+    /// popping the error object from the top of the stack.
+    pub(crate) fn pop_from_stack(lua: *mut lua_State, status: i32) -> LuaError {
+        let message = error_message(lua, -1);
+        unsafe { td_clua::lua_pop(lua, 1) };
+        let data = state::state_data(lua);
+        let traceback = data.traceback.take();
+        let frames = mem::take(&mut data.frames);
+        match data.limits.exceeded.take() {
+            Some(Exceeded::Instructions) if status == td_clua::LUA_ERRRUN => return LuaError::InstructionLimit,
+            Some(Exceeded::Timeout) if status == td_clua::LUA_ERRRUN => return LuaError::Timeout,
+            _ => {}
+        }
+        match status {
+            td_clua::LUA_ERRSYNTAX => LuaError::Syntax(message),
+            td_clua::LUA_ERRMEM => LuaError::Memory(message),
+            td_clua::LUA_ERRERR => LuaError::ErrorHandler(message),
+            _ => LuaError::Runtime {
+                message,
+                traceback,
+                frames,
+            },
+        }
+    }
+}
+
+impl fmt::Display for LuaError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            LuaError::Syntax(ref msg) => write!(f, "syntax error: {}", msg),
+            LuaError::Runtime { ref message, ref traceback, .. } => {
+                write!(f, "runtime error: {}", message)?;
+                if let Some(ref traceback) = *traceback {
+                    write!(f, "\n{}", traceback)?;
+                }
+                Ok(())
+            }
+            LuaError::Memory(ref msg) => write!(f, "memory error: {}", msg),
+            LuaError::Timeout => write!(f, "execution timed out"),
+            LuaError::InstructionLimit => write!(f, "instruction limit exceeded"),
+            LuaError::ErrorHandler(ref msg) => write!(f, "error in error handler: {}", msg),
+            LuaError::Conversion { ref expected, ref actual } => {
+                write!(f, "cannot convert lua {} to {}", actual, expected)
+            }
+            #[cfg(feature = "serde")]
+            LuaError::Serde(ref msg) => write!(f, "serde error: {}", msg),
+        }
+    }
+}
+
+impl Error for LuaError {}
+
+/// Error of a Rust function called with a bad argument, to return as the `Err` of its result.
+///
+/// It is displayed like the errors of the Lua standard library:
+/// `bad argument #2 to 'foo' (number expected, got string)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArgumentError {
+    /// Position of the argument, starting at 1.
+    pub position: i32,
+    /// Name of the function, left out of the message when `None`.
+    pub function: Option<String>,
+    pub message: String,
+}
+
+impl ArgumentError {
+    pub fn new<S>(position: i32, message: S) -> ArgumentError where S: Into<String> {
+        ArgumentError {
+            position,
+            function: None,
+            message: message.into(),
+        }
+    }
+
+    /// An argument of type `actual` given where a value of type `expected` was expected.
+    pub fn type_mismatch(position: i32, expected: &str, actual: &str) -> ArgumentError {
+        ArgumentError::new(position, format!("{} expected, got {}", expected, actual))
+    }
+
+    /// Names the function in the message.
+    pub fn function<S>(mut self, name: S) -> ArgumentError where S: Into<String> {
+        self.function = Some(name.into());
+        self
+    }
+}
+
+impl fmt::Display for ArgumentError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "bad argument #{}", self.position)?;
+        if let Some(ref function) = self.function {
+            write!(f, " to '{}'", function)?;
+        }
+        write!(f, " ({})", self.message)
+    }
+}
+
+impl Error for ArgumentError {}
+
+/// Returns the name of the type of the value at the given index.
+pub(crate) fn type_name(lua: *mut lua_State, index: i32) -> String {
+    let tp = unsafe { td_clua::lua_type(lua, index) };
+    type_name_of(lua, tp)
+}
+
+/// Returns the name of a type code as given by `lua_type`.
+pub(crate) fn type_name_of(lua: *mut lua_State, tp: i32) -> String {
+    let name = unsafe { CStr::from_ptr(td_clua::lua_typename(lua, tp)) };
+    name.to_string_lossy().into_owned()
+}
+
+/// Describes the error object at the given index.
+pub(crate) fn error_message(lua: *mut lua_State, index: i32) -> String {
+    match unsafe { td_clua::lua_type(lua, index) } {
+        td_clua::LUA_TSTRING | td_clua::LUA_TNUMBER => {
+            let mut size = 0;
+            let data = unsafe { td_clua::lua_tolstring(lua, index, &mut size) };
+            let bytes = unsafe { std::slice::from_raw_parts(data as *const u8, size) };
+            String::from_utf8_lossy(bytes).into_owned()
+        }
+        _ => format!("(error object is a {} value)", type_name(lua, index)),
+    }
+}
+
+/// Message handler of every protected call made by this library.
+///
+/// Records the traceback of the failing code and forwards the error to the handler set with
+/// `Lua::set_error_handler`, then returns the error object unchanged.
+pub(crate) extern "C" fn error_handler(lua: *mut lua_State) -> libc::c_int {
+    panic::protect(lua, || {
+        let message = error_message(lua, 1);
+        report_error(lua, lua, &message, 1);
+    });
+    1
+}
+
+/// Records the traceback and the stack frames of `thread` starting at `level`, for the next
+/// `LuaError::pop_from_stack`, and forwards the error to the handler set with
+/// `Lua::set_error_handler`. The traceback is built on the stack of `lua`.
+pub(crate) fn report_error(lua: *mut lua_State, thread: *mut lua_State, message: &str, level: i32) {
+    let traceback = unsafe {
+        td_clua::luaL_traceback(lua, thread, ptr::null(), level);
+        let traceback = error_message(lua, -1);
+        td_clua::lua_pop(lua, 1);
+        traceback
+    };
+
+    // the handler is taken out while it runs, so it may replace itself
+    if let Some(mut handler) = state::state_data(lua).error_handler.take() {
+        handler(message, &traceback);
+        let data = state::state_data(lua);
+        if data.error_handler.is_none() {
+            data.error_handler = Some(handler);
+        }
+    }
+    let frames = capture_stack(thread, level);
+    let data = state::state_data(lua);
+    data.traceback = Some(traceback);
+    data.frames = frames;
+}
+
+/// Walks the call stack of `lua` starting at `level` and describes every frame.
+pub(crate) fn capture_stack(lua: *mut lua_State, level: i32) -> Vec<StackFrame> {
+    let capture_locals = state::state_data(lua).capture_locals;
+    let what = CString::new("Sln").unwrap();
+    let mut frames = Vec::new();
+    let mut level = level;
+    let mut ar = td_clua::lua_Debug::default();
+    while unsafe { td_clua::lua_getstack(lua, level, &mut ar) } != 0 {
+        unsafe { td_clua::lua_getinfo(lua, what.as_ptr(), &mut ar) };
+        let mut locals = Vec::new();
+        if capture_locals {
+            let mut n = 1;
+            loop {
+                let name = unsafe { td_clua::lua_getlocal(lua, &ar, n) };
+                if name.is_null() {
+                    break;
+                }
+                let name = c_string(name);
+                // skip internal variables such as "(*temporary)"
+                if !name.starts_with('(') {
+                    locals.push((name, describe_value(lua, -1)));
+                }
+                unsafe { td_clua::lua_pop(lua, 1) };
+                n += 1;
+            }
+        }
+        let short_src = unsafe { CStr::from_ptr(ar.short_src.as_ptr()) };
+        frames.push(StackFrame {
+            source: short_src.to_string_lossy().into_owned(),
+            line: if ar.currentline >= 0 { Some(ar.currentline as u32) } else { None },
+            name: if ar.name.is_null() { None } else { Some(c_string(ar.name)) },
+            namewhat: c_string(ar.namewhat),
+            what: c_string(ar.what),
+            locals,
+        });
+        level += 1;
+    }
+    frames
+}
+
+fn c_string(ptr: *const libc::c_char) -> String {
+    if ptr.is_null() {
+        return String::new();
+    }
+    unsafe { CStr::from_ptr(ptr) }.to_string_lossy().into_owned()
+}
+
+/// Printable form of the value at the given index, without converting it in place.
+fn describe_value(lua: *mut lua_State, index: i32) -> String {
+    match unsafe { td_clua::lua_type(lua, index) } {
+        td_clua::LUA_TNIL => "nil".to_string(),
+        td_clua::LUA_TBOOLEAN => (unsafe { td_clua::lua_toboolean(lua, index) } != 0).to_string(),
+        td_clua::LUA_TNUMBER | td_clua::LUA_TSTRING => unsafe {
+            td_clua::lua_pushvalue(lua, index);
+            let value = error_message(lua, -1);
+            td_clua::lua_pop(lua, 1);
+            value
+        },
+        _ => type_name(lua, index),
+    }
+}
@@ -2,43 +2,69 @@ use libc;
 use td_clua::{self, lua_State};
 use LuaRead;
 use LuaPush;
+use panic;
+use error::{self, ArgumentError};
 
+use std::cell::Cell;
+use std::ffi::{CStr, CString};
+use std::fmt::Display;
 use std::marker::PhantomData;
 use std::mem;
+use std::panic::{catch_unwind, resume_unwind, AssertUnwindSafe};
 use std::ptr;
+use std::thread;
 
 macro_rules! impl_function {
-    ($name:ident, $($p:ident),*) => (
+    ($name:ident, $shared:ident, $($p:ident),*) => (
 /// Wraps a type that implements `FnMut` so that it can be used by hlua.
 ///
 /// This is only needed because of a limitation in Rust's inferrence system.
+/// Calling the function again while it is running, for instance from Lua code it calls,
+/// raises a Lua error.
         pub fn $name<Z, R $(, $p)*>(f: Z) -> Function<Z, ($($p,)*), R> where Z: FnMut($($p),*) -> R {
             Function {
                 function: f,
                 marker: PhantomData,
             }
         }
+
+/// Wraps a type that implements `Fn`, which unlike `FnMut` can be called again by Lua
+/// while it is already running.
+        pub fn $shared<Z, R $(, $p)*>(f: Z) -> SharedFunction<Z, ($($p,)*), R> where Z: Fn($($p),*) -> R {
+            SharedFunction {
+                function: f,
+                marker: PhantomData,
+            }
+        }
     )
 }
 
-impl_function!(function0,);
-impl_function!(function1, A);
-impl_function!(function2, A, B);
-impl_function!(function3, A, B, C);
-impl_function!(function4, A, B, C, D);
-impl_function!(function5, A, B, C, D, E);
-impl_function!(function6, A, B, C, D, E, F);
-impl_function!(function7, A, B, C, D, E, F, G);
-impl_function!(function8, A, B, C, D, E, F, G, H);
-impl_function!(function9, A, B, C, D, E, F, G, H, I);
-impl_function!(function10, A, B, C, D, E, F, G, H, I, J);
+impl_function!(function0, shared_function0,);
+impl_function!(function1, shared_function1, A);
+impl_function!(function2, shared_function2, A, B);
+impl_function!(function3, shared_function3, A, B, C);
+impl_function!(function4, shared_function4, A, B, C, D);
+impl_function!(function5, shared_function5, A, B, C, D, E);
+impl_function!(function6, shared_function6, A, B, C, D, E, F);
+impl_function!(function7, shared_function7, A, B, C, D, E, F, G);
+impl_function!(function8, shared_function8, A, B, C, D, E, F, G, H);
+impl_function!(function9, shared_function9, A, B, C, D, E, F, G, H, I);
+impl_function!(function10, shared_function10, A, B, C, D, E, F, G, H, I, J);
 
 /// Opaque type containing a Rust function or closure.
+///
+/// Once pushed, the closure is owned by Lua and dropped when the Lua function is collected.
 pub struct Function<F, P, R> {
     function: F,
     marker: PhantomData<(P, R)>,
 }
 
+/// Opaque type containing a Rust function or closure implementing `Fn`.
+pub struct SharedFunction<F, P, R> {
+    function: F,
+    marker: PhantomData<(P, R)>,
+}
+
 /// Trait implemented on `Function` to mimic `FnMut`.
 pub trait FunctionExt<P> {
     type Output;
@@ -46,38 +72,33 @@ pub trait FunctionExt<P> {
     fn call_mut(&mut self, params: P) -> Self::Output;
 }
 
-macro_rules! impl_function_ext {
-    () => (
-        impl<Z, R> FunctionExt<()> for Function<Z, (), R> where Z: FnMut() -> R {
-            type Output = R;
+/// Trait implemented on `SharedFunction` to mimic `Fn`.
+pub trait SharedFunctionExt<P> {
+    type Output;
 
-            #[allow(non_snake_case)]
-            fn call_mut(&mut self, _: ()) -> Self::Output {
-                (self.function)()
-            }
+    fn call(&self, params: P) -> Self::Output;
+}
+
+/// Number of arguments taken by a function, to pick the overload of a constructor defined
+/// with `LuaStruct::constructor`.
+pub trait FunctionArity {
+    const ARITY: i32;
+}
+
+macro_rules! count_one {
+    ($p:ident) => (1)
+}
+
+macro_rules! impl_function_ext {
+    ($($p:ident),*) => (
+        impl<Z, R $(,$p)*> FunctionArity for Function<Z, ($($p,)*), R> {
+            const ARITY: i32 = 0 $(+ count_one!($p))*;
         }
 
-        impl<Z, R> LuaPush for Function<Z, (), R>
-                where Z: FnMut() -> R,
-                      R: LuaPush + 'static
-        {
-            fn push_to_lua(self, lua: *mut lua_State) -> i32 {
-                unsafe {
-                    // pushing the function pointer as a userdata
-                    let lua_data = td_clua::lua_newuserdata(lua, mem::size_of::<Z>() as libc::size_t);
-                    let lua_data: *mut Z = mem::transmute(lua_data);
-                    ptr::write(lua_data, self.function);
-
-                    // pushing wrapper as a closure
-                    let wrapper: extern fn(*mut td_clua::lua_State) -> libc::c_int = wrapper::<Self, _, R>;
-                    td_clua::lua_pushcclosure(lua, wrapper, 1);
-                    1
-                }
-            }
+        impl<Z, R $(,$p)*> FunctionArity for SharedFunction<Z, ($($p,)*), R> {
+            const ARITY: i32 = 0 $(+ count_one!($p))*;
         }
-    );
 
-    ($($p:ident),+) => (
         impl<Z, R $(,$p)*> FunctionExt<($($p,)*)> for Function<Z, ($($p,)*), R> where Z: FnMut($($p),*) -> R {
             type Output = R;
 
@@ -88,23 +109,35 @@ macro_rules! impl_function_ext {
             }
         }
 
-        impl<Z, R $(,$p: 'static)+> LuaPush for Function<Z, ($($p,)*), R>
-                where Z: FnMut($($p),*) -> R,
+        impl<Z, R $(,$p)*> SharedFunctionExt<($($p,)*)> for SharedFunction<Z, ($($p,)*), R> where Z: Fn($($p),*) -> R {
+            type Output = R;
+
+            #[allow(non_snake_case)]
+            fn call(&self, params: ($($p,)*)) -> Self::Output {
+                let ($($p,)*) = params;
+                (self.function)($($p),*)
+            }
+        }
+
+        impl<Z, R $(,$p: 'static)*> LuaPush for Function<Z, ($($p,)*), R>
+                where Z: FnMut($($p),*) -> R + 'static,
+                      ($($p,)*): LuaRead,
+                      R: CallbackReturn + 'static
+        {
+            fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+                push_closure(lua, self, wrapper::<Self, _, R>);
+                1
+            }
+        }
+
+        impl<Z, R $(,$p: 'static)*> LuaPush for SharedFunction<Z, ($($p,)*), R>
+                where Z: Fn($($p),*) -> R + 'static,
                       ($($p,)*): LuaRead,
-                      R: LuaPush + 'static
+                      R: CallbackReturn + 'static
         {
             fn push_to_lua(self, lua: *mut lua_State) -> i32 {
-                unsafe {
-                    // pushing the function pointer as a userdata
-                    let lua_data = td_clua::lua_newuserdata(lua, mem::size_of::<Z>() as libc::size_t);
-                    let lua_data: *mut Z = mem::transmute(lua_data);
-                    ptr::write(lua_data, self.function);
-
-                    // pushing wrapper as a closure
-                    let wrapper: extern fn(*mut td_clua::lua_State) -> libc::c_int = wrapper::<Self, _, R>;
-                    td_clua::lua_pushcclosure(lua, wrapper, 1);
-                    1
-                }
+                push_closure(lua, self, shared_wrapper::<Self, _, R>);
+                1
             }
         }
     )
@@ -122,36 +155,279 @@ impl_function_ext!(A, B, C, D, E, F, G, H);
 impl_function_ext!(A, B, C, D, E, F, G, H, I);
 impl_function_ext!(A, B, C, D, E, F, G, H, I, J);
 
-// this function is called when Lua wants to call one of our functions
-extern "C" fn wrapper<T, P, R>(lua: *mut td_clua::lua_State) -> libc::c_int
-    where T: FunctionExt<P, Output = R>,
-          P: LuaRead + 'static,
-          R: LuaPush
+/// Userdata kept as the upvalue of the Lua closure calling a Rust function.
+pub(crate) struct FunctionData<T> {
+    /// set while the function runs, to refuse calling a `FnMut` again
+    busy: Cell<bool>,
+    function: T,
+}
+
+// the body of a function run by `call_protected`, and what it returned or the panic
+struct ProtectedCall<F, R> {
+    body: Option<F>,
+    result: Option<thread::Result<R>>,
+}
+
+extern "C" fn run_protected<F, R>(lua: *mut lua_State) -> libc::c_int
+    where F: FnOnce() -> R
 {
-    // loading the object that we want to call from the Lua context
-    let data_raw = unsafe { td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1)) };
-    let data: &mut T = unsafe { mem::transmute(data_raw) };
-
-    // trying to read the arguments
-    let arguments_count = unsafe { td_clua::lua_gettop(lua) } as i32;
-    let args = match LuaRead::lua_read_at_position(lua, -arguments_count as libc::c_int) {      // TODO: what if the user has the wrong params?
-        Some(a) => a,
-        _ => {
-            let err_msg = format!("wrong parameter types for callback function arguments_count \
-                                   is {}",
-                                  arguments_count);
-            err_msg.push_to_lua(lua);
-            unsafe {
-                td_clua::lua_error(lua);
-            }
+    unsafe {
+        let call = td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1));
+        let call = &mut *(call as *mut ProtectedCall<F, R>);
+        if let Some(body) = call.body.take() {
+            call.result = Some(catch_unwind(AssertUnwindSafe(body)));
+        }
+    }
+    0
+}
+
+// runs `body` in a protected Lua call, so an error raised by Lua while it runs comes back
+// here instead of jumping over the caller, the error object being left on the stack
+//
+// the values of the stack are given to the protected call, so `body` finds them at the same
+// indices, the stack must have room for them
+fn call_protected<F, R>(lua: *mut lua_State, body: F) -> Option<thread::Result<R>>
+    where F: FnOnce() -> R
+{
+    let mut call = ProtectedCall { body: Some(body), result: None };
+    unsafe {
+        let top = td_clua::lua_gettop(lua);
+        td_clua::lua_pushlightuserdata(lua, &mut call as *mut ProtectedCall<F, R> as *mut libc::c_void);
+        td_clua::lua_pushcclosure(lua, run_protected::<F, R>, 1);
+        for index in 1..=top {
+            td_clua::lua_pushvalue(lua, index);
+        }
+        if td_clua::lua_pcall(lua, top, 0, 0) != td_clua::LUA_OK {
+            return None;
+        }
+    }
+    call.result
+}
+
+extern "C" fn destroy_function<T>(lua: *mut lua_State) -> libc::c_int {
+    panic::protect(lua, || unsafe {
+        let data = td_clua::lua_touserdata(lua, 1);
+        ptr::drop_in_place(data as *mut FunctionData<T>);
+    });
+    0
+}
+
+/// Pushes a C closure calling `wrapper` with the function as its upvalue, the function
+/// being dropped when the closure is collected.
+pub(crate) fn push_closure<T>(lua: *mut lua_State, function: T, wrapper: td_clua::lua_CFunction) {
+    unsafe {
+        let size = mem::size_of::<FunctionData<T>>();
+        let lua_data = td_clua::lua_newuserdata(lua, size as libc::size_t) as *mut FunctionData<T>;
+        ptr::write(lua_data, FunctionData { busy: Cell::new(false), function });
+
+        td_clua::lua_newtable(lua);
+        let gc = CString::new("__gc").unwrap();
+        td_clua::lua_pushcfunction(lua, destroy_function::<T>);
+        td_clua::lua_setfield(lua, -2, gc.as_ptr());
+        td_clua::lua_setmetatable(lua, -2);
+
+        td_clua::lua_pushcclosure(lua, wrapper, 1);
+    }
+}
+
+// returns the function of the running C closure made by `push_closure`
+fn function_data<T>(lua: *mut lua_State) -> *mut FunctionData<T> {
+    unsafe { td_clua::lua_touserdata(lua, td_clua::lua_upvalueindex(1)) as *mut FunctionData<T> }
+}
+
+/// Reads the arguments and calls the `FnMut` function of the running C closure made by
+/// `push_closure`.
+///
+/// A function already running is refused with a Lua error, since calling it would alias
+/// its mutable state. It runs in a protected call, so that it is marked as done before a Lua
+/// error raised while it runs, or a panic, goes on.
+pub(crate) fn call_function<T, P>(lua: *mut lua_State) -> T::Output
+    where T: FunctionExt<P>,
+          P: LuaRead
+{
+    let data = function_data::<T>(lua);
+    let busy = unsafe { &(*data).busy };
+    if busy.get() {
+        raise_error(lua, "attempt to call a Rust function which is already running".to_string());
+    }
+    if unsafe { td_clua::lua_checkstack(lua, td_clua::lua_gettop(lua) + 2) } == 0 {
+        raise_error(lua, "stack overflow".to_string());
+    }
+    let args = read_arguments(lua);
+    busy.set(true);
+    let result = call_protected(lua, move || unsafe { (*data).function.call_mut(args) });
+    busy.set(false);
+    match result {
+        Some(Ok(output)) => output,
+        Some(Err(payload)) => resume_unwind(payload),
+        None => unsafe {
+            td_clua::lua_error(lua);
             unreachable!()
+        },
+    }
+}
+
+/// Values returned by a Rust function to Lua.
+///
+/// Implemented by every `LuaPush` type, which values are returned to the caller,
+/// and by `Outcome` for the functions that may suspend the calling coroutine.
+pub trait CallbackReturn {
+    /// Pushes the values on the stack and tells what to do with them.
+    fn push_return(self, lua: *mut lua_State) -> Pushed;
+}
+
+/// What the function called by Lua does with the values on the top of the stack.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Pushed {
+    /// Returns that many values.
+    Return(i32),
+    /// Yields that many values.
+    Yield(i32),
+    /// Raises the error object on the top of the stack.
+    Error,
+}
+
+impl<T> CallbackReturn for T where T: LuaPush {
+    fn push_return(self, lua: *mut lua_State) -> Pushed {
+        Pushed::Return(self.push_to_lua(lua))
+    }
+}
+
+/// Return value of a Rust function that can suspend the coroutine calling it,
+/// to implement blocking-looking primitives such as `wait(seconds)`.
+///
+/// The yielded values are received by whoever resumed the coroutine, and the values passed
+/// to the next resume are returned by the function to the script.
+/// Yielding outside of a coroutine raises a Lua error.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome<R, Y = ()> {
+    /// Returns the values to the caller.
+    Return(R),
+    /// Suspends the calling coroutine, yielding the values.
+    Yield(Y),
+}
+
+impl<R, Y> CallbackReturn for Outcome<R, Y> where R: LuaPush, Y: LuaPush {
+    fn push_return(self, lua: *mut lua_State) -> Pushed {
+        match self {
+            Outcome::Return(values) => Pushed::Return(values.push_to_lua(lua)),
+            Outcome::Yield(values) => Pushed::Yield(values.push_to_lua(lua)),
         }
+    }
+}
+
+/// A function returning `Err` raises a Lua error with the message of the error, once the
+/// Rust values of the call have been dropped.
+impl<R, E> CallbackReturn for Result<R, E> where R: CallbackReturn, E: Display {
+    fn push_return(self, lua: *mut lua_State) -> Pushed {
+        match self {
+            Ok(values) => values.push_return(lua),
+            Err(err) => {
+                err.to_string().push_to_lua(lua);
+                Pushed::Error
+            }
+        }
+    }
+}
+
+// called when a coroutine suspended by one of our functions is resumed,
+// the values below `ctx` belong to the function and the others were passed to resume
+extern "C" fn resume_continuation(lua: *mut lua_State, _status: libc::c_int, ctx: td_clua::lua_KContext) -> libc::c_int {
+    unsafe { td_clua::lua_gettop(lua) - ctx as libc::c_int }
+}
+
+/// Ends a function called by Lua, every Rust value must have been dropped before
+/// since yielding and raising an error don't return.
+pub(crate) fn finish_call(lua: *mut lua_State, pushed: Pushed) -> libc::c_int {
+    match pushed {
+        Pushed::Return(nb) => nb,
+        Pushed::Yield(nb) => unsafe {
+            let base = td_clua::lua_gettop(lua) - nb;
+            td_clua::lua_yieldk(lua, nb, base as td_clua::lua_KContext, Some(resume_continuation))
+        },
+        Pushed::Error => unsafe { td_clua::lua_error(lua) },
+    }
+}
+
+/// Reads the arguments of a function called by Lua, raising a Lua error telling which argument
+/// doesn't match.
+pub(crate) fn read_arguments<P>(lua: *mut lua_State) -> P where P: LuaRead {
+    match P::lua_read_checked(lua, 1) {
+        Ok(a) => a,
+        Err(err) => {
+            // the message is built in its own scope so that nothing else is left to drop when
+            // the error is raised
+            let message = {
+                let (offset, expected) = err;
+                let position = offset + 1;
+                let actual = error::type_name(lua, position);
+                argument_error(lua, position, &format!("{} expected, got {}", expected, actual))
+            };
+            raise_error(lua, message)
+        }
+    }
+}
 
+/// Message of a bad argument of the running function, naming it like `luaL_argerror`.
+pub(crate) fn argument_error(lua: *mut lua_State, position: i32, message: &str) -> String {
+    let mut ar = td_clua::lua_Debug::default();
+    let what = CString::new("n").unwrap();
+    if unsafe { td_clua::lua_getstack(lua, 0, &mut ar) } == 0 {
+        return ArgumentError::new(position, message).to_string();
+    }
+    unsafe { td_clua::lua_getinfo(lua, what.as_ptr(), &mut ar) };
+    let name = if ar.name.is_null() {
+        "?".to_string()
+    } else {
+        unsafe { CStr::from_ptr(ar.name) }.to_string_lossy().into_owned()
     };
+    // the object of a method call isn't counted
+    let mut position = position;
+    if unsafe { CStr::from_ptr(ar.namewhat) }.to_bytes() == b"method" {
+        position -= 1;
+        if position == 0 {
+            return format!("calling '{}' on bad self ({})", name, message);
+        }
+    }
+    ArgumentError::new(position, message).function(name).to_string()
+}
+
+/// Raises a Lua error with the message from a function called by Lua, every Rust value
+/// must have been dropped before since the error doesn't return.
+pub(crate) fn raise_error(lua: *mut lua_State, message: String) -> ! {
+    message.push_to_lua(lua);
+    unsafe {
+        td_clua::lua_error(lua);
+    }
+    unreachable!()
+}
+
+// this function is called when Lua wants to call one of our functions
+pub(crate) extern "C" fn wrapper<T, P, R>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: FunctionExt<P, Output = R>,
+          P: LuaRead + 'static,
+          R: CallbackReturn
+{
+    let pushed = panic::protect(lua, || {
+        let ret_value = call_function::<T, P>(lua);
 
-    let ret_value = data.call_mut(args);
+        // pushing back the result of the function on the stack
+        ret_value.push_return(lua)
+    });
+    finish_call(lua, pushed)
+}
 
-    // pushing back the result of the function on the stack
-    let nb = ret_value.push_to_lua(lua);
-    nb as libc::c_int
+// same as `wrapper` for the `Fn` functions, which may run several times at once
+extern "C" fn shared_wrapper<T, P, R>(lua: *mut td_clua::lua_State) -> libc::c_int
+    where T: SharedFunctionExt<P, Output = R>,
+          P: LuaRead + 'static,
+          R: CallbackReturn
+{
+    let pushed = panic::protect(lua, || {
+        let data = function_data::<T>(lua);
+        let args = read_arguments(lua);
+        let ret_value = unsafe { (*data).function.call(args) };
+        ret_value.push_return(lua)
+    });
+    finish_call(lua, pushed)
 }
@@ -0,0 +1,111 @@
+use std::marker::PhantomData;
+
+use td_clua::{self, lua_State};
+
+use Lua;
+
+/// Garbage collection algorithm.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GcMode {
+    /// Collects in small steps interleaved with the program, the only mode of Lua 5.3.
+    Incremental,
+    /// Collects the young objects more often, available from Lua 5.4.
+    Generational,
+}
+
+/// Controls the garbage collector of a state, returned by `Lua::gc`.
+///
+/// The collector can be stopped and then run in steps when the program has spare time:
+///
+/// ```ignore
+/// lua.gc().stop();
+/// // ... in the idle time of each frame
+/// lua.gc().step(64);
+/// ```
+pub struct GcControl<'a> {
+    lua: *mut lua_State,
+    marker: PhantomData<&'a mut Lua>,
+}
+
+impl<'a> GcControl<'a> {
+    pub(crate) fn new(lua: &'a mut Lua) -> GcControl<'a> {
+        GcControl {
+            lua: lua.state(),
+            marker: PhantomData,
+        }
+    }
+
+    fn gc(&self, what: i32, data: i32) -> i32 {
+        unsafe { td_clua::lua_gc(self.lua, what, data) }
+    }
+
+    /// Stops the automatic collection, it only runs through `step` and `collect` until
+    /// `restart` is called.
+    pub fn stop(&self) {
+        self.gc(td_clua::LUA_GCSTOP, 0);
+    }
+
+    pub fn restart(&self) {
+        self.gc(td_clua::LUA_GCRESTART, 0);
+    }
+
+    /// Whether the automatic collection is running, it is unless stopped.
+    pub fn is_running(&self) -> bool {
+        self.gc(td_clua::LUA_GCISRUNNING, 0) != 0
+    }
+
+    /// Runs a full collection cycle.
+    pub fn collect(&self) {
+        self.gc(td_clua::LUA_GCCOLLECT, 0);
+    }
+
+    /// Does an amount of collection work matching the allocation of `kbytes` kilobytes, or a
+    /// basic step for 0. Returns true when the step finished a collection cycle.
+    pub fn step(&self, kbytes: i32) -> bool {
+        self.gc(td_clua::LUA_GCSTEP, kbytes) != 0
+    }
+
+    /// Sets how long the collector waits before a new cycle, as a percentage of the memory in
+    /// use after the previous one (200 waits for the memory to double), returning the previous
+    /// value.
+    pub fn set_pause(&self, percent: i32) -> i32 {
+        self.gc(td_clua::LUA_GCSETPAUSE, percent)
+    }
+
+    /// Sets the speed of the collector relative to memory allocation as a percentage, larger
+    /// values making each step do more work, returning the previous value.
+    pub fn set_step_multiplier(&self, percent: i32) -> i32 {
+        self.gc(td_clua::LUA_GCSETSTEPMUL, percent)
+    }
+
+    /// Switches the collector to `mode`, returns false when the Lua version doesn't have it.
+    pub fn set_mode(&self, mode: GcMode) -> bool {
+        let what = match mode {
+            GcMode::Incremental => td_clua::LUA_GCINC,
+            GcMode::Generational => td_clua::LUA_GCGEN,
+        };
+        // Lua 5.3 reports unknown options with -1 and is always incremental
+        match self.gc(what, 0) {
+            -1 => mode == GcMode::Incremental,
+            _ => true,
+        }
+    }
+
+    /// Memory in use by the state in kilobytes, rounded down.
+    pub fn kbytes(&self) -> usize {
+        self.gc(td_clua::LUA_GCCOUNT, 0) as usize
+    }
+
+    /// Memory in use by the state in bytes.
+    pub fn bytes(&self) -> usize {
+        bytes(self.lua)
+    }
+}
+
+/// Memory in use by the state in bytes.
+pub(crate) fn bytes(lua: *mut lua_State) -> usize {
+    unsafe {
+        td_clua::lua_gc(lua, td_clua::LUA_GCCOUNT, 0) as usize * 1024
+            + td_clua::lua_gc(lua, td_clua::LUA_GCCOUNTB, 0) as usize
+    }
+}
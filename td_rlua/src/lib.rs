@@ -1,10 +1,19 @@
 extern crate td_clua;
 extern crate libc;
+#[cfg(feature = "serde")]
+extern crate serde;
+#[cfg(feature = "derive")]
+extern crate td_rlua_derive;
 
+use std::alloc::{GlobalAlloc, System};
+use std::any::type_name;
 use std::borrow::Borrow;
 use std::ffi::{CStr, CString};
 use std::io::prelude::*;
 use std::fs::File;
+use std::time::Duration;
+
+use memory::MemoryState;
 
 macro_rules! unwrap_or {
     ($expr:expr, $or:expr) => (
@@ -21,13 +30,42 @@ pub mod functions;
 pub mod userdata;
 pub mod tuples;
 pub mod rust_tables;
+pub mod error;
+pub mod lua_ref;
+pub mod lua_value;
+pub mod lua_thread;
+pub mod sandbox;
+pub mod gc;
+pub mod variadic;
+#[cfg(feature = "serde")]
+pub mod lua_serde;
+#[cfg(feature = "async")]
+pub mod lua_async;
 mod hotfix;
+mod limits;
+mod memory;
+mod panic;
+mod state;
 
 pub use td_clua::*;
-pub use functions::{function0, function1, function2, function3, function4, function5, function6, function7, function8, function9, function10, Function};
-pub use userdata::{push_userdata, push_lightuserdata, read_userdata, LuaStruct, NewStruct};
+pub use functions::{function0, function1, function2, function3, function4, function5, function6, function7, function8, function9, function10, Function, Outcome};
+pub use functions::{shared_function0, shared_function1, shared_function2, shared_function3, shared_function4, shared_function5, shared_function6, shared_function7, shared_function8, shared_function9, shared_function10, SharedFunction};
+pub use userdata::{push_userdata, push_lightuserdata, push_struct, read_userdata, read_userdata_ref, LuaStruct, NewStruct, LuaMethods, MetaMethod};
 pub use lua_tables::LuaTable;
 pub use values::RawString;
+pub use error::{ArgumentError, LuaError, LuaResult, StackFrame};
+pub use lua_ref::{LuaRef, LuaFunctionRef, LuaTableRef};
+pub use lua_value::LuaValue;
+pub use lua_thread::{LuaThread, ThreadStatus, Resume};
+pub use sandbox::{StdLib, Sandbox};
+pub use gc::{GcControl, GcMode};
+pub use variadic::{Variadic, MultiValue};
+#[cfg(feature = "serde")]
+pub use lua_serde::{to_lua, from_lua, Serde};
+#[cfg(feature = "async")]
+pub use lua_async::{async_function0, async_function1, async_function2, async_function3, async_function4, async_function5, async_function6, async_function7, async_function8, async_function9, async_function10, AsyncFunction, AsyncCall};
+#[cfg(feature = "derive")]
+pub use td_rlua_derive::{LuaPush, LuaRead, lua_methods};
 pub struct Lua {
     lua: *mut lua_State,
     own: bool,
@@ -71,28 +109,23 @@ impl LuaGuard {
 
 
 macro_rules! impl_exec_func {
-    ($name:ident, $($p:ident),*) => (
-        #[allow(non_snake_case, unused_mut)]
-        pub fn $name<Z, $($p),*>(&mut self, func_name : Z, $($p : $p, )*) -> i32 where Z: Borrow<str>, $($p : LuaPush),* {
-            let func_name = CString::new(func_name.borrow()).unwrap();
-            unsafe {
-                let state = self.state();
-                let error = CString::new("error_handle").unwrap();
-                lua_getglobal(state, error.as_ptr());
-                td_clua::lua_getglobal(state, func_name.as_ptr());
-
+    ($name:ident, $try_name:ident, $($p:ident),*) => (
+        #[allow(non_snake_case, unused_mut, unused_variables, clippy::too_many_arguments)]
+        pub fn $try_name<Z, $($p),*>(&mut self, func_name : Z, $($p : $p, )*) -> LuaResult<()> where Z: Borrow<str>, $($p : LuaPush),* {
+            self.call_path(func_name.borrow(), |state| {
                 let mut index = 0;
                 $(
-                    index += $p.push_to_lua(self.state());
+                    index += $p.push_to_lua(state);
                 )*
+                index
+            }, 0).map(|_| ())
+        }
 
-                let success = td_clua::lua_pcall(state, index, 0, -1 * index - 2);
-                if success != 0 {
-                    let _guard = LuaGuard::new(state, 2);
-                    return success;
-                }
-                td_clua::lua_pop(state, 1);
-                success
+        #[allow(non_snake_case, clippy::too_many_arguments)]
+        pub fn $name<Z, $($p),*>(&mut self, func_name : Z, $($p : $p, )*) -> i32 where Z: Borrow<str>, $($p : LuaPush),* {
+            match self.$try_name(func_name, $($p, )*) {
+                Ok(()) => 0,
+                Err(err) => err.status(),
             }
         }
     )
@@ -100,31 +133,81 @@ macro_rules! impl_exec_func {
 
 
 macro_rules! impl_read_func {
-    ($name:ident, $($p:ident),*) => (
-        #[allow(non_snake_case, unused_mut)]
-        pub fn $name<'a, Z, R, $($p),*>(&'a mut self, func_name : Z, $($p : $p, )*) -> Option<R> where Z: Borrow<str>, R : LuaRead, $($p : LuaPush),* {
-            let func_name = CString::new(func_name.borrow()).unwrap();
-            unsafe {
-                let state = self.state();
-                let error = CString::new("error_handle").unwrap();
-                lua_getglobal(state, error.as_ptr());
-                td_clua::lua_getglobal(state, func_name.as_ptr());
-
+    ($name:ident, $try_name:ident, $($p:ident),*) => (
+        #[allow(non_snake_case, unused_mut, unused_variables, clippy::too_many_arguments)]
+        pub fn $try_name<Z, R, $($p),*>(&mut self, func_name : Z, $($p : $p, )*) -> LuaResult<R> where Z: Borrow<str>, R : LuaRead, $($p : LuaPush),* {
+            self.call_path(func_name.borrow(), |state| {
                 let mut index = 0;
                 $(
-                    index += $p.push_to_lua(self.state());
+                    index += $p.push_to_lua(state);
                 )*
+                index
+            }, 1)?;
+            read_results(self.state(), 1)
+        }
+
+        #[allow(non_snake_case, clippy::too_many_arguments)]
+        pub fn $name<'a, Z, R, $($p),*>(&'a mut self, func_name : Z, $($p : $p, )*) -> Option<R> where Z: Borrow<str>, R : LuaRead, $($p : LuaPush),* {
+            self.$try_name(func_name, $($p, )*).ok()
+        }
+    )
+}
+
+/// Calls the function placed below the `nargs` arguments on the top of the stack in
+/// protected mode, using `error::error_handler` as message handler.
+///
+/// On success the `nresults` results are left on the stack, on failure the error object
+/// is popped and returned.
+pub(crate) fn pcall(state: *mut lua_State, nargs: i32, nresults: i32) -> LuaResult<()> {
+    unsafe {
+        let base = td_clua::lua_gettop(state) - nargs;
+        td_clua::lua_pushcfunction(state, error::error_handler);
+        td_clua::lua_insert(state, base);
+        let status = td_clua::lua_pcall(state, nargs, nresults, base);
+        let result = if status != 0 {
+            Err(LuaError::pop_from_stack(state, status))
+        } else {
+            Ok(())
+        };
+        td_clua::lua_remove(state, base);
+        if let Err(ref err) = result {
+            panic::resume_panic(state, err);
+        }
+        result
+    }
+}
 
-                let success = td_clua::lua_pcall(state, index, 1, -1 * index - 2);
-                if success != 0 {
-                    let _guard = LuaGuard::new(state, 2);
-                    return None;
+/// Whether indexing the value at the given index can't raise an error.
+fn can_index(state: *mut lua_State, index: i32) -> bool {
+    unsafe {
+        match td_clua::lua_type(state, index) {
+            td_clua::LUA_TTABLE => true,
+            td_clua::LUA_TUSERDATA => {
+                if td_clua::lua_getmetatable(state, index) == 0 {
+                    return false;
                 }
-                td_clua::lua_remove(state, -2);
-                LuaRead::lua_read_with_pop(state, -1, 1)
+                let name = CString::new("__index").unwrap();
+                td_clua::lua_getfield(state, -1, name.as_ptr());
+                let has_index = !td_clua::lua_isnil(state, -1);
+                td_clua::lua_pop(state, 2);
+                has_index
             }
+            _ => false,
         }
-    )
+    }
+}
+
+/// Reads the `count` results left on the top of the stack by a successful call and pops them.
+pub(crate) fn read_results<R>(state: *mut lua_State, count: i32) -> LuaResult<R> where R: LuaRead {
+    let index = unsafe { td_clua::lua_gettop(state) } - count + 1;
+    let tp = unsafe { td_clua::lua_type(state, index) };
+    match LuaRead::lua_read_with_pop(state, index, count) {
+        Some(value) => Ok(value),
+        None => Err(LuaError::Conversion {
+            expected: type_name::<R>().to_string(),
+            actual: error::type_name_of(state, tp),
+        }),
+    }
 }
 
 impl Lua {
@@ -139,7 +222,35 @@ impl Lua {
         if lua.is_null() {
             panic!("lua_newstate failed");
         }
+        Lua::from_new_state(lua)
+    }
 
+    /// Builds a new Lua context allocating its memory with `allocator`, and keeping track of
+    /// the memory it uses.
+    ///
+    /// # Panic
+    ///
+    /// The function panics if the state can't be allocated.
+    pub fn with_allocator<A>(allocator: A) -> Lua where A: GlobalAlloc + 'static {
+        let memory = Box::into_raw(Box::new(MemoryState::new(Box::new(allocator))));
+        let lua = unsafe { td_clua::lua_newstate(memory::allocate, memory as *mut libc::c_void) };
+        if lua.is_null() {
+            unsafe { drop(Box::from_raw(memory)) };
+            panic!("lua_newstate failed");
+        }
+        state::state_data(lua).memory = Some(memory);
+        Lua::from_new_state(lua)
+    }
+
+    /// Builds a new Lua context using the system allocator, where allocations fail once the
+    /// state uses more than `bytes`, reported as `LuaError::Memory`.
+    pub fn with_memory_limit(bytes: usize) -> Lua {
+        let mut lua = Lua::with_allocator(System);
+        lua.set_memory_limit(Some(bytes));
+        lua
+    }
+
+    fn from_new_state(lua: *mut lua_State) -> Lua {
         // called whenever lua encounters an unexpected error.
         extern "C" fn panic(lua: *mut td_clua::lua_State) -> libc::c_int {
             let err = unsafe { td_clua::lua_tostring(lua, -1) };
@@ -148,20 +259,48 @@ impl Lua {
             panic!("PANIC: unprotected error in call to Lua API ({})\n", err);
         }
 
-        extern "C" fn error_handle(lua: *mut td_clua::lua_State) -> libc::c_int {
-            let err = unsafe { td_clua::lua_tostring(lua, -1) };
-            let err = unsafe { CStr::from_ptr(err) };
-            let err = String::from_utf8_lossy(&err.to_bytes());
-            println!("error:{}", err);
-            0
-        }
-
         unsafe { td_clua::lua_atpanic(lua, panic) };
-        let mut lua = Lua {
+        Lua {
             lua: lua,
             own: true,
-        };
-        lua.register("error_handle", error_handle);
+        }
+    }
+
+    /// Changes the memory limit of a context made by `with_allocator` or `with_memory_limit`.
+    /// The memory already used is kept even if it is over the new limit.
+    ///
+    /// # Panic
+    ///
+    /// The function panics if the context uses the default allocator.
+    pub fn set_memory_limit(&mut self, limit: Option<usize>) {
+        match state::state_data(self.state()).memory {
+            Some(memory) => unsafe { (*memory).limit = limit },
+            None => panic!("the memory limit needs a state made by Lua::with_allocator"),
+        }
+    }
+
+    /// Bytes currently allocated by the context.
+    pub fn memory_used(&self) -> usize {
+        match state::state_data(self.lua).memory {
+            Some(memory) => unsafe { (*memory).used },
+            None => gc::bytes(self.lua),
+        }
+    }
+
+    /// Most bytes allocated at once by a context made by `with_allocator` or
+    /// `with_memory_limit`, `None` for the others.
+    pub fn peak_memory_used(&self) -> Option<usize> {
+        state::state_data(self.lua).memory.map(|memory| unsafe { (*memory).peak })
+    }
+
+    /// Builds a new Lua context with only the given standard libraries opened.
+    ///
+    /// ```ignore
+    /// let lua = Lua::new_with_libs(StdLib::BASE | StdLib::STRING | StdLib::TABLE);
+    /// ```
+    pub fn new_with_libs(libs: StdLib) -> Lua {
+        let mut lua = Lua::new();
+        lua.open_libs(libs);
         lua
     }
 
@@ -200,10 +339,79 @@ impl Lua {
         0
     }
 
+    /// Sets the function called whenever code run by one of the `exec` functions fails.
+    ///
+    /// The handler receives the error message and a stack traceback of the failing code.
+    /// It is kept in the registry, so scripts can neither call nor replace it.
+    pub fn set_error_handler<F>(&mut self, handler: F)
+                             where F: FnMut(&str, &str) + 'static
+    {
+        state::state_data(self.state()).error_handler = Some(Box::new(handler));
+    }
+
+    /// Removes the error handler, errors are then only reported through the returned `LuaError`.
+    pub fn clear_error_handler(&mut self) {
+        state::state_data(self.state()).error_handler = None;
+    }
+
+    /// Enables recording the local variables of every stack frame attached to runtime errors.
+    pub fn set_capture_locals(&mut self, capture: bool) {
+        state::state_data(self.state()).capture_locals = capture;
+    }
+
+    /// Makes a panic in a Rust function called by Lua resume on the Rust side once the
+    /// protected call running it returns, instead of being reported as a `LuaError::Runtime`
+    /// with the panic message. Either way the script can catch the panic with `pcall`.
+    pub fn set_resume_panics(&mut self, resume: bool) {
+        state::state_data(self.state()).resume_panics = resume;
+    }
+
+    /// Aborts the running code with `LuaError::InstructionLimit` once it has executed about
+    /// `limit` instructions, checked every thousand instructions at most.
+    ///
+    /// The count is shared by every call until `reset_limits` is called, so a script
+    /// catching the error with `pcall` can't keep running.
+    pub fn set_instruction_limit(&mut self, limit: u64) {
+        state::state_data(self.state()).limits.instruction_limit = Some(limit);
+        limits::update_hook(self.state());
+    }
+
+    /// Aborts the running code with `LuaError::Timeout` once `timeout` has elapsed since
+    /// this call or the last `reset_limits`.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        state::state_data(self.state()).limits.timeout = Some(timeout);
+        limits::update_hook(self.state());
+    }
+
+    /// Restarts the instruction count and the timeout, usually before each call.
+    pub fn reset_limits(&mut self) {
+        limits::update_hook(self.state());
+    }
+
+    /// Removes the instruction limit and the timeout.
+    pub fn remove_limits(&mut self) {
+        let limits = &mut state::state_data(self.state()).limits;
+        limits.instruction_limit = None;
+        limits.timeout = None;
+        limits::update_hook(self.state());
+    }
+
+    /// Instructions counted since the limits were set or reset, updated at each check.
+    pub fn instruction_count(&mut self) -> u64 {
+        state::state_data(self.state()).limits.instructions
+    }
+
     /// Opens all standard Lua libraries.
-    /// This is done by calling `luaL_openlibs`.
+    /// This is done by calling `luaL_openlibs`, then `coroutine.resume` and `coroutine.wrap`
+    /// are replaced by versions applying the limits to the coroutines they resume.
     pub fn openlibs(&mut self) {
         unsafe { td_clua::luaL_openlibs(self.lua) }
+        limits::limit_coroutines(self.lua);
+    }
+
+    /// Opens some of the standard libraries, see `StdLib`.
+    pub fn open_libs(&mut self, libs: StdLib) {
+        sandbox::open_libs(self.lua, libs)
     }
 
     /// Reads the value of a global variable.
@@ -224,47 +432,199 @@ impl Lua {
         unsafe { td_clua::lua_setglobal(self.lua, index.as_ptr()); }
     }
 
+    /// Pushes the function designated by `path`, followed by the object it is called on
+    /// for method calls.
+    ///
+    /// Returns the number of arguments pushed along with the function (1 for method calls).
+    fn push_function(&mut self, path: &str) -> i32 {
+        let state = self.state();
+        let (object, method) = match path.rfind(':') {
+            Some(pos) => (&path[..pos], Some(&path[pos + 1..])),
+            None => (path, None),
+        };
+
+        let mut names = object.split('.');
+        let first = CString::new(names.next().unwrap_or("")).unwrap();
+        unsafe {
+            td_clua::lua_getglobal(state, first.as_ptr());
+            for name in names {
+                let name = CString::new(name).unwrap();
+                if can_index(state, -1) {
+                    td_clua::lua_getfield(state, -1, name.as_ptr());
+                    td_clua::lua_remove(state, -2);
+                } else {
+                    // calling nil reports the missing function
+                    td_clua::lua_pop(state, 1);
+                    td_clua::lua_pushnil(state);
+                }
+            }
+
+            match method {
+                Some(method) if can_index(state, -1) => {
+                    let method = CString::new(method).unwrap();
+                    td_clua::lua_getfield(state, -1, method.as_ptr());
+                    td_clua::lua_insert(state, -2);
+                    1
+                }
+                Some(_) => {
+                    td_clua::lua_pop(state, 1);
+                    td_clua::lua_pushnil(state);
+                    0
+                }
+                None => 0,
+            }
+        }
+    }
+
+    /// Calls the function designated by `path` with the arguments pushed by `push_args`.
+    ///
+    /// Leaves `nresults` results on the stack, or all of them with `MULTRET`, and returns
+    /// their number.
+    fn call_path<F>(&mut self, path: &str, push_args: F, nresults: i32) -> LuaResult<i32>
+                 where F: FnOnce(*mut lua_State) -> i32
+    {
+        let state = self.state();
+        let top = unsafe { td_clua::lua_gettop(state) };
+        let nself = self.push_function(path);
+        // room for the arguments of a tuple, a `Variadic` checks the stack for its own values
+        unsafe { td_clua::lua_checkstack(state, td_clua::LUA_MINSTACK) };
+        let nargs = push_args(state) + nself;
+        pcall(state, nargs, nresults)?;
+        Ok(unsafe { td_clua::lua_gettop(state) } - top)
+    }
+
+    /// Calls a Lua function and reads the values it returns.
+    ///
+    /// `name` is the name of a global function, a dotted path such as `"mod.sub.fn"`, or a
+    /// method call such as `"obj:method"` which passes `obj` as first argument.
+    ///
+    /// `args` is pushed with `LuaPush`, so a tuple gives one argument per element, as does a
+    /// `Variadic` or a `MultiValue`, while a `Vec` is a single table argument. `call_values`
+    /// takes the arguments only known at runtime as a `Vec<LuaValue>`.
+    /// Every value returned by the function is kept, a tuple `R` reads them in order.
+    pub fn call<R>(&mut self, name: &str, args: impl LuaPush) -> LuaResult<R>
+                where R: LuaRead
+    {
+        let count = self.call_path(name, |state| args.push_to_lua(state), td_clua::MULTRET)?;
+        read_results(self.state(), count)
+    }
+
+    /// Calls a Lua function like `call` with arguments only known at runtime, each value of
+    /// `args` being a separate argument.
+    pub fn call_values<R>(&mut self, name: &str, args: Vec<LuaValue>) -> LuaResult<R>
+                       where R: LuaRead
+    {
+        let state = self.state();
+        let room = args.len().saturating_add(td_clua::LUA_MINSTACK as usize);
+        if room > libc::c_int::MAX as usize || unsafe { td_clua::lua_checkstack(state, room as libc::c_int) } == 0 {
+            return Err(LuaError::Runtime {
+                message: format!("stack overflow pushing {} arguments", args.len()),
+                traceback: None,
+                frames: Vec::new(),
+            });
+        }
+        let count = self.call_path(name, |state| {
+            args.into_iter().map(|value| value.push_to_lua(state)).sum()
+        }, td_clua::MULTRET)?;
+        read_results(self.state(), count)
+    }
+
+    /// Calls a function from a new coroutine, returning a future that resumes it each time
+    /// it is polled, so the script can wait for the functions made by `async_function0` ...
+    ///
+    /// Errors, including a missing function, are reported when the future completes.
+    #[cfg(feature = "async")]
+    pub fn call_async<R>(&mut self, name: &str, args: impl LuaPush) -> lua_async::AsyncCall<R>
+                      where R: LuaRead
+    {
+        let state = self.state();
+        let nself = self.push_function(name);
+        unsafe {
+            let thread = td_clua::lua_newthread(state);
+            let handle = LuaThread::lua_read_at_position(state, -1).unwrap();
+            td_clua::lua_pop(state, 1);
+            td_clua::lua_xmove(state, thread, 1 + nself);
+            let nargs = args.push_to_lua(thread) + nself;
+            lua_async::AsyncCall::new(handle, nargs)
+        }
+    }
+
+    /// Executes a chunk of Lua code and reads the value it returns.
+    pub fn try_exec_string<I, R>(&mut self, index : I) -> LuaResult<R>
+                            where I: Borrow<str>, R : LuaRead
+    {
+        let index = CString::new(index.borrow()).unwrap();
+        unsafe {
+            let state = self.state();
+            let status = td_clua::luaL_loadstring(state, index.as_ptr());
+            if status != 0 {
+                return Err(LuaError::pop_from_stack(state, status));
+            }
+            pcall(state, 0, 1)?;
+            read_results(state, 1)
+        }
+    }
+
     pub fn exec_string<'a, I, R>(&'a mut self, index : I) -> Option<R>
                             where I: Borrow<str>, R : LuaRead
+    {
+        self.try_exec_string(index).ok()
+    }
+
+    /// Executes a chunk of Lua code with the given table as its global environment, usually
+    /// made by a `Sandbox`, and reads the value it returns.
+    ///
+    /// Precompiled chunks are refused.
+    pub fn try_exec_string_in<I, R>(&mut self, env: &LuaTableRef, index : I) -> LuaResult<R>
+                            where I: Borrow<str>, R : LuaRead
     {
         let index = CString::new(index.borrow()).unwrap();
+        let mode = CString::new("t").unwrap();
         unsafe {
             let state = self.state();
-            let error = CString::new("error_handle").unwrap();
-            td_clua::lua_getglobal(state, error.as_ptr());
-            td_clua::luaL_loadstring(state, index.as_ptr());
-            let success = td_clua::lua_pcall(state, 0, 1, -2);
-            if success != 0 {
-                let _guard = LuaGuard::new(self.lua, 2);
-                return None;
+            let len = index.as_bytes().len();
+            let status = td_clua::luaL_loadbufferx(state, index.as_ptr(), len, index.as_ptr(),
+                                                   mode.as_ptr());
+            if status != 0 {
+                return Err(LuaError::pop_from_stack(state, status));
             }
-            td_clua::lua_remove(state, -2);
-            LuaRead::lua_read_with_pop(state, -1, 1)
+            // the first upvalue of a main chunk is its _ENV
+            env.push_to_lua(state);
+            td_clua::lua_setupvalue(state, -2, 1);
+            let _methods = sandbox::StringMethods::restrict(state, env);
+            pcall(state, 0, 1)?;
+            read_results(state, 1)
         }
     }
 
-    pub fn exec_func<'a, I, R>(&'a mut self, index : I) -> Option<R>
+    pub fn exec_string_in<I, R>(&mut self, env: &LuaTableRef, index : I) -> Option<R>
+                            where I: Borrow<str>, R : LuaRead
+    {
+        self.try_exec_string_in(env, index).ok()
+    }
+
+    /// Calls a global function with every value currently on the stack as arguments
+    /// and reads the value it returns.
+    pub fn try_exec_func<I, R>(&mut self, index : I) -> LuaResult<R>
                             where I: Borrow<str>, R : LuaRead
     {
         let index = CString::new(index.borrow()).unwrap();
         unsafe {
             let state = self.state();
-            let error = CString::new("error_handle").unwrap();
             let top = td_clua::lua_gettop(state);
             td_clua::lua_getglobal(state, index.as_ptr());
             td_clua::lua_insert(state, -top - 1);
-            td_clua::lua_getglobal(state, error.as_ptr());
-            td_clua::lua_insert(state, -top - 2);
-            let success = td_clua::lua_pcall(state, top, 1, -top-2);
-            if success != 0 {
-                let _guard = LuaGuard::new(self.lua, 2);
-                return None;
-            }
-            td_clua::lua_remove(state, -2);
-            LuaRead::lua_read_with_pop(state, -1, 1)
+            pcall(state, top, 1)?;
+            read_results(state, 1)
         }
     }
 
+    pub fn exec_func<'a, I, R>(&'a mut self, index : I) -> Option<R>
+                            where I: Borrow<str>, R : LuaRead
+    {
+        self.try_exec_func(index).ok()
+    }
+
     /// Inserts an empty table, then loads it.
     pub fn empty_table<I>(&mut self, index: I) -> LuaTable
                               where I: Borrow<str>
@@ -285,7 +645,7 @@ impl Lua {
             td_clua::lua_getglobal(state, package.as_ptr());
             td_clua::lua_getfield(state, -1, searchers.as_ptr());
             td_clua::lua_pushcfunction(state, func);
-            let mut i = (td_clua::lua_rawlen(state, -2) + 1) as i32;
+            let mut i = (td_clua::lua_rawlen(state, -2) + 1) as td_clua::lua_Integer;
             while i > 2 {
                 td_clua::lua_rawgeti(state, -2, i - 1);                               
                 td_clua::lua_rawseti(state, -3, i);
@@ -379,30 +739,35 @@ impl Lua {
         unsafe { td_clua::lua_gc(self.state(), td_clua::LUA_GCCOLLECT, 0) as i32 } 
     }
 
-    impl_exec_func!(exec_func0, );
-    impl_exec_func!(exec_func1, A);
-    impl_exec_func!(exec_func2, A, B);
-    impl_exec_func!(exec_func3, A, B, C);
-    impl_exec_func!(exec_func4, A, B, C, D);
-    impl_exec_func!(exec_func5, A, B, C, D, E);
-    impl_exec_func!(exec_func6, A, B, C, D, E, F);
-    impl_exec_func!(exec_func7, A, B, C, D, E, F, G);
-    impl_exec_func!(exec_func8, A, B, C, D, E, F, G, H);
-    impl_exec_func!(exec_func9, A, B, C, D, E, F, G, H, I);
-    impl_exec_func!(exec_func10, A, B, C, D, E, F, G, H, I, J);
-
-
-    impl_read_func!(read_func0, );
-    impl_read_func!(read_func1, A);
-    impl_read_func!(read_func2, A, B);
-    impl_read_func!(read_func3, A, B, C);
-    impl_read_func!(read_func4, A, B, C, D);
-    impl_read_func!(read_func5, A, B, C, D, E);
-    impl_read_func!(read_func6, A, B, C, D, E, F);
-    impl_read_func!(read_func7, A, B, C, D, E, F, G);
-    impl_read_func!(read_func8, A, B, C, D, E, F, G, H);
-    impl_read_func!(read_func9, A, B, C, D, E, F, G, H, I);
-    impl_read_func!(read_func10, A, B, C, D, E, F, G, H, I, J);
+    /// Gives control over the garbage collector.
+    pub fn gc(&mut self) -> GcControl<'_> {
+        GcControl::new(self)
+    }
+
+    impl_exec_func!(exec_func0, try_exec_func0, );
+    impl_exec_func!(exec_func1, try_exec_func1, A);
+    impl_exec_func!(exec_func2, try_exec_func2, A, B);
+    impl_exec_func!(exec_func3, try_exec_func3, A, B, C);
+    impl_exec_func!(exec_func4, try_exec_func4, A, B, C, D);
+    impl_exec_func!(exec_func5, try_exec_func5, A, B, C, D, E);
+    impl_exec_func!(exec_func6, try_exec_func6, A, B, C, D, E, F);
+    impl_exec_func!(exec_func7, try_exec_func7, A, B, C, D, E, F, G);
+    impl_exec_func!(exec_func8, try_exec_func8, A, B, C, D, E, F, G, H);
+    impl_exec_func!(exec_func9, try_exec_func9, A, B, C, D, E, F, G, H, I);
+    impl_exec_func!(exec_func10, try_exec_func10, A, B, C, D, E, F, G, H, I, J);
+
+
+    impl_read_func!(read_func0, try_read_func0, );
+    impl_read_func!(read_func1, try_read_func1, A);
+    impl_read_func!(read_func2, try_read_func2, A, B);
+    impl_read_func!(read_func3, try_read_func3, A, B, C);
+    impl_read_func!(read_func4, try_read_func4, A, B, C, D);
+    impl_read_func!(read_func5, try_read_func5, A, B, C, D, E);
+    impl_read_func!(read_func6, try_read_func6, A, B, C, D, E, F);
+    impl_read_func!(read_func7, try_read_func7, A, B, C, D, E, F, G);
+    impl_read_func!(read_func8, try_read_func8, A, B, C, D, E, F, G, H);
+    impl_read_func!(read_func9, try_read_func9, A, B, C, D, E, F, G, H, I);
+    impl_read_func!(read_func10, try_read_func10, A, B, C, D, E, F, G, H, I, J);
 
 }
 
@@ -442,12 +807,31 @@ pub trait LuaRead: Sized {
 
     fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, pop: i32) -> Option<Self>;
 
+    /// Reads the data from Lua at a given position like `lua_read_at_position`, on failure
+    /// returns the offset from `index` of the value that could not be read, with the name of
+    /// the type expected there.
+    ///
+    /// Used to report the bad argument of a function called by Lua.
+    fn lua_read_checked(lua: *mut lua_State, index: i32) -> Result<Self, (i32, String)> {
+        LuaRead::lua_read_at_position(lua, index).ok_or_else(|| (0, Self::lua_type_name()))
+    }
+
+    /// Name of the type in the errors, the Lua type for the basic values like `"number"`
+    /// and the Rust type otherwise.
+    fn lua_type_name() -> String {
+        type_name::<Self>().to_string()
+    }
 }
 
 impl Drop for Lua {
     fn drop(&mut self) {
         if self.own {
+            // the allocator is still needed to free the state
+            let memory = state::state_data(self.lua).memory;
             unsafe { td_clua::lua_close(self.lua) }
+            if let Some(memory) = memory {
+                unsafe { drop(Box::from_raw(memory)) };
+            }
         }
     }
 }
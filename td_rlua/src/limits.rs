@@ -0,0 +1,172 @@
+use std::ffi::CString;
+use std::time::{Duration, Instant};
+
+use libc;
+use td_clua::{self, lua_State};
+
+use state;
+
+/// Number of instructions between two checks of the limits.
+const HOOK_STEP: u64 = 1000;
+
+/// Limit that aborted the running code.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Exceeded {
+    Instructions,
+    Timeout,
+}
+
+/// Execution limits of a state, checked by a count hook.
+#[derive(Debug, Default)]
+pub struct Limits {
+    pub instruction_limit: Option<u64>,
+    /// instructions executed since the limits were last reset
+    pub instructions: u64,
+    pub timeout: Option<Duration>,
+    pub deadline: Option<Instant>,
+    /// set by the hook when it raises an error, for `LuaError::pop_from_stack`
+    pub exceeded: Option<Exceeded>,
+}
+
+impl Limits {
+    /// Restarts the instruction count and the timeout.
+    pub fn reset(&mut self) {
+        self.instructions = 0;
+        self.deadline = self.timeout.map(|timeout| Instant::now() + timeout);
+        self.exceeded = None;
+    }
+
+    fn check(&mut self, executed: u64) -> Option<Exceeded> {
+        self.instructions += executed;
+        if let Some(limit) = self.instruction_limit {
+            if self.instructions >= limit {
+                return Some(Exceeded::Instructions);
+            }
+        }
+        match self.deadline {
+            Some(deadline) if Instant::now() >= deadline => Some(Exceeded::Timeout),
+            _ => None,
+        }
+    }
+}
+
+extern "C" fn limit_hook(lua: *mut lua_State, _ar: *mut td_clua::lua_Debug) {
+    let executed = unsafe { td_clua::lua_gethookcount(lua) } as u64;
+    let limits = &mut state::state_data(lua).limits;
+    let exceeded = match limits.check(executed) {
+        Some(exceeded) => exceeded,
+        None => return,
+    };
+    limits.exceeded = Some(exceeded);
+    // checking every instruction from now on, so the error can't be caught by a `pcall`
+    // and the code resumed until the next check
+    unsafe { td_clua::lua_sethook(lua, limit_hook, td_clua::LUA_MASKCOUNT, 1) };
+    let message: &[u8] = match exceeded {
+        Exceeded::Instructions => b"instruction limit exceeded\0",
+        Exceeded::Timeout => b"execution timed out\0",
+    };
+    unsafe {
+        td_clua::lua_pushstring(lua, message.as_ptr() as *const libc::c_char);
+        td_clua::lua_error(lua);
+    }
+}
+
+// mask and count of the hook checking the limits, every instruction once they are exceeded
+fn hook_mask(limits: &Limits) -> (libc::c_int, libc::c_int) {
+    match (limits.instruction_limit, limits.timeout) {
+        (None, None) => (0, 0),
+        _ if limits.exceeded.is_some() => (td_clua::LUA_MASKCOUNT, 1),
+        (limit, _) => {
+            let step = limit.map_or(HOOK_STEP, |limit| limit.clamp(1, HOOK_STEP));
+            (td_clua::LUA_MASKCOUNT, step as libc::c_int)
+        }
+    }
+}
+
+/// Installs the hook checking the limits of the state, or removes it when there are none.
+///
+/// The hook is set on `lua`, coroutines created afterwards inherit it and the others get it
+/// from `refresh_hook` when they are resumed.
+pub fn update_hook(lua: *mut lua_State) {
+    let limits = &mut state::state_data(lua).limits;
+    limits.reset();
+    let (mask, count) = hook_mask(limits);
+    unsafe { td_clua::lua_sethook(lua, limit_hook, mask, count) };
+}
+
+/// Gives a coroutine about to be resumed the hook of the current limits, unless it has a hook
+/// of its own, for instance set with `debug.sethook`.
+pub(crate) fn refresh_hook(thread: *mut lua_State) {
+    let (mask, count) = hook_mask(&state::state_data(thread).limits);
+    unsafe {
+        let current = td_clua::lua_gethookmask(thread);
+        if current != 0 && td_clua::lua_gethook(thread) as usize != limit_hook as td_clua::lua_Hook as usize {
+            return;
+        }
+        if current != mask || td_clua::lua_gethookcount(thread) != count {
+            td_clua::lua_sethook(thread, limit_hook, mask, count);
+        }
+    }
+}
+
+// `coroutine.resume` refreshing the hook of the coroutine, the original function being
+// its upvalue
+extern "C" fn limited_resume(lua: *mut lua_State) -> libc::c_int {
+    unsafe {
+        let thread = td_clua::lua_tothread(lua, 1);
+        if !thread.is_null() {
+            refresh_hook(thread);
+        }
+        td_clua::lua_pushvalue(lua, td_clua::lua_upvalueindex(1));
+        td_clua::lua_insert(lua, 1);
+        td_clua::lua_call(lua, td_clua::lua_gettop(lua) - 1, td_clua::MULTRET);
+        td_clua::lua_gettop(lua)
+    }
+}
+
+// `coroutine.wrap` resuming with `limited_resume`, errors are raised again without position
+// like the original
+static LIMITED_WRAP: &str = r"
+    local create, resume = ...
+    local function finish(ok, ...)
+        if not ok then
+            error((...), 0)
+        end
+        return ...
+    end
+    return function(f)
+        local co = create(f)
+        return function(...)
+            return finish(resume(co, ...))
+        end
+    end
+";
+
+/// Replaces `resume` and `wrap` of the loaded `coroutine` library, so the coroutines resumed
+/// by the scripts get the hook of the limits set after they were created.
+pub(crate) fn limit_coroutines(lua: *mut lua_State) {
+    let loaded = CString::new("_LOADED").unwrap();
+    let coroutine = CString::new("coroutine").unwrap();
+    let create = CString::new("create").unwrap();
+    let resume = CString::new("resume").unwrap();
+    let wrap = CString::new("wrap").unwrap();
+    let code = CString::new(LIMITED_WRAP).unwrap();
+    unsafe {
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, loaded.as_ptr());
+        td_clua::lua_getfield(lua, -1, coroutine.as_ptr());
+        if !td_clua::lua_istable(lua, -1) {
+            td_clua::lua_pop(lua, 2);
+            return;
+        }
+        td_clua::lua_getfield(lua, -1, resume.as_ptr());
+        td_clua::lua_pushcclosure(lua, limited_resume, 1);
+        td_clua::lua_setfield(lua, -2, resume.as_ptr());
+
+        td_clua::luaL_loadstring(lua, code.as_ptr());
+        td_clua::lua_getfield(lua, -2, create.as_ptr());
+        td_clua::lua_getfield(lua, -3, resume.as_ptr());
+        td_clua::lua_call(lua, 2, 1);
+        td_clua::lua_setfield(lua, -2, wrap.as_ptr());
+        td_clua::lua_pop(lua, 2);
+    }
+}
@@ -0,0 +1,218 @@
+//! Rust futures called from Lua coroutines.
+//!
+//! A function registered with `async_function0` .. `async_function10` returns a future.
+//! When Lua calls it, the calling coroutine is suspended until the future completes and
+//! its output is then returned to the script. The coroutine must be the one driven by
+//! `Lua::call_async`, which returns a future resuming it each time it is polled.
+
+use std::ffi::CString;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::mem;
+use std::pin::Pin;
+use std::ptr;
+use std::task::{Context, Poll};
+
+use libc;
+use td_clua::{self, lua_State};
+
+use functions::{self, Function, FunctionExt, Pushed};
+use panic;
+use LuaPush;
+use LuaRead;
+use LuaResult;
+use LuaThread;
+use Resume;
+
+/// Pushes the output of a completed future, returning the number of values.
+type PushOutput = Box<dyn FnOnce(*mut lua_State) -> i32>;
+
+type PendingFuture = Pin<Box<dyn Future<Output = PushOutput>>>;
+
+static PENDING_KEY: &str = "__td_rlua_pending_future";
+
+macro_rules! impl_async_function {
+    ($name:ident, $function:ident, $($p:ident),*) => (
+/// Wraps a closure returning a future so that it can be called by Lua from the coroutine of
+/// `Lua::call_async`.
+        pub fn $name<Z, R $(, $p)*>(f: Z) -> AsyncFunction<Z, ($($p,)*), R>
+            where Z: FnMut($($p),*) -> R
+        {
+            AsyncFunction { function: functions::$function(f) }
+        }
+    )
+}
+
+impl_async_function!(async_function0, function0,);
+impl_async_function!(async_function1, function1, A);
+impl_async_function!(async_function2, function2, A, B);
+impl_async_function!(async_function3, function3, A, B, C);
+impl_async_function!(async_function4, function4, A, B, C, D);
+impl_async_function!(async_function5, function5, A, B, C, D, E);
+impl_async_function!(async_function6, function6, A, B, C, D, E, F);
+impl_async_function!(async_function7, function7, A, B, C, D, E, F, G);
+impl_async_function!(async_function8, function8, A, B, C, D, E, F, G, H);
+impl_async_function!(async_function9, function9, A, B, C, D, E, F, G, H, I);
+impl_async_function!(async_function10, function10, A, B, C, D, E, F, G, H, I, J);
+
+/// Opaque type containing a Rust function or closure returning a future.
+pub struct AsyncFunction<Z, P, F> {
+    function: Function<Z, P, F>,
+}
+
+impl<Z, P, F, R> LuaPush for AsyncFunction<Z, P, F>
+    where Function<Z, P, F>: FunctionExt<P, Output = F>,
+          Z: 'static,
+          P: LuaRead + 'static,
+          F: Future<Output = R> + 'static,
+          R: LuaPush + 'static
+{
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        functions::push_closure(lua, self.function, async_wrapper::<Function<Z, P, F>, P, F, R>);
+        1
+    }
+}
+
+// called by Lua, it yields the future to the `AsyncCall` driving the coroutine
+extern "C" fn async_wrapper<T, P, F, R>(lua: *mut lua_State) -> libc::c_int
+    where T: FunctionExt<P, Output = F>,
+          P: LuaRead + 'static,
+          F: Future<Output = R> + 'static,
+          R: LuaPush + 'static
+{
+    let future = panic::protect(lua, || functions::call_function::<T, P>(lua));
+    push_pending(lua, Box::pin(Completion { future }));
+    functions::finish_call(lua, Pushed::Yield(1))
+}
+
+/// Turns the output of a future into the values returned to Lua.
+struct Completion<F> {
+    future: F,
+}
+
+impl<F, R> Future for Completion<F> where F: Future<Output = R>, R: LuaPush + 'static {
+    type Output = PushOutput;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<PushOutput> {
+        // the future is never moved out of the pinned completion
+        let future = unsafe { self.map_unchecked_mut(|completion| &mut completion.future) };
+        future.poll(cx).map(|output| {
+            Box::new(move |lua: *mut lua_State| output.push_to_lua(lua)) as PushOutput
+        })
+    }
+}
+
+extern "C" fn pending_gc(lua: *mut lua_State) -> libc::c_int {
+    panic::protect(lua, || unsafe {
+        let obj = td_clua::lua_touserdata(lua, 1);
+        ptr::drop_in_place(obj as *mut Option<PendingFuture>);
+    });
+    0
+}
+
+// pushes the metatable shared by the pending futures
+fn push_pending_metatable(lua: *mut lua_State) {
+    let key = CString::new(PENDING_KEY).unwrap();
+    unsafe {
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr());
+        if td_clua::lua_type(lua, -1) == td_clua::LUA_TTABLE {
+            return;
+        }
+        td_clua::lua_pop(lua, 1);
+        td_clua::lua_newtable(lua);
+        let gc = CString::new("__gc").unwrap();
+        td_clua::lua_pushcfunction(lua, pending_gc);
+        td_clua::lua_setfield(lua, -2, gc.as_ptr());
+        td_clua::lua_pushvalue(lua, -1);
+        td_clua::lua_setfield(lua, td_clua::LUA_REGISTRYINDEX, key.as_ptr());
+    }
+}
+
+fn push_pending(lua: *mut lua_State, future: PendingFuture) {
+    unsafe {
+        let size = mem::size_of::<Option<PendingFuture>>();
+        let lua_data = td_clua::lua_newuserdata(lua, size as libc::size_t);
+        ptr::write(lua_data as *mut Option<PendingFuture>, Some(future));
+        push_pending_metatable(lua);
+        td_clua::lua_setmetatable(lua, -2);
+    }
+}
+
+// takes the future out of the value at `index` if it is a pending future
+fn take_pending(lua: *mut lua_State, index: i32) -> Option<PendingFuture> {
+    unsafe {
+        if td_clua::lua_type(lua, index) != td_clua::LUA_TUSERDATA ||
+           td_clua::lua_getmetatable(lua, index) == 0 {
+            return None;
+        }
+        push_pending_metatable(lua);
+        let is_pending = td_clua::lua_rawequal(lua, -1, -2) != 0;
+        td_clua::lua_pop(lua, 2);
+        if !is_pending {
+            return None;
+        }
+        let data = td_clua::lua_touserdata(lua, index) as *mut Option<PendingFuture>;
+        (*data).take()
+    }
+}
+
+/// Future returned by `Lua::call_async`, resolving to the values returned by the function.
+///
+/// Each poll resumes the coroutine until it waits for a pending Rust future or finishes.
+/// A plain `coroutine.yield()` from the script hands control back to the executor, and the
+/// coroutine is resumed without values on the next poll.
+pub struct AsyncCall<R> {
+    thread: LuaThread,
+    nargs: i32,
+    pending: Option<PendingFuture>,
+    marker: PhantomData<fn() -> R>,
+}
+
+impl<R> AsyncCall<R> {
+    /// The coroutine must hold the function followed by its `nargs` arguments.
+    pub(crate) fn new(thread: LuaThread, nargs: i32) -> AsyncCall<R> {
+        AsyncCall {
+            thread,
+            nargs,
+            pending: None,
+            marker: PhantomData,
+        }
+    }
+}
+
+impl<R> Future for AsyncCall<R> where R: LuaRead {
+    type Output = LuaResult<R>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<LuaResult<R>> {
+        let call = self.get_mut();
+        let co = call.thread.state();
+        loop {
+            if let Some(mut future) = call.pending.take() {
+                match future.as_mut().poll(cx) {
+                    Poll::Pending => {
+                        call.pending = Some(future);
+                        return Poll::Pending;
+                    }
+                    Poll::Ready(push) => call.nargs = push(co),
+                }
+            }
+
+            let nargs = mem::replace(&mut call.nargs, 0);
+            match call.thread.resume_pushed(nargs) {
+                Err(err) => return Poll::Ready(Err(err)),
+                Ok(Resume::Finished(count)) => return Poll::Ready(::read_results(co, count)),
+                Ok(Resume::Yield(count)) => {
+                    let future = if count == 1 { take_pending(co, -1) } else { None };
+                    unsafe { td_clua::lua_pop(co, count) };
+                    match future {
+                        Some(future) => call.pending = Some(future),
+                        None => {
+                            cx.waker().wake_by_ref();
+                            return Poll::Pending;
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
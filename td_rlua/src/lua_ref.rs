@@ -0,0 +1,326 @@
+use std::cell::Cell;
+use std::fmt;
+use std::rc::Rc;
+
+use td_clua::{self, lua_State};
+
+use state;
+use LuaError;
+use LuaPush;
+use LuaRead;
+use LuaResult;
+
+/// Reference to a Lua value kept alive in the registry.
+///
+/// Unlike `LuaTable`, a `LuaRef` doesn't live on the stack, so it can be stored anywhere
+/// on the Rust side. The value is released when the reference is dropped. A reference may
+/// outlive the Lua context: once the state is closed, it no longer touches it, and pushing
+/// the value panics.
+pub struct LuaRef {
+    lua: *mut lua_State,
+    reference: i32,
+    closed: Rc<Cell<bool>>,
+}
+
+/// Returns the main thread of the state `lua` belongs to.
+///
+/// References are bound to it because a coroutine may be collected before them.
+pub(crate) fn main_thread(lua: *mut lua_State) -> *mut lua_State {
+    unsafe {
+        td_clua::lua_rawgeti(lua, td_clua::LUA_REGISTRYINDEX, td_clua::LUA_RIDX_MAINTHREAD);
+        let main = td_clua::lua_tothread(lua, -1);
+        td_clua::lua_pop(lua, 1);
+        main
+    }
+}
+
+impl LuaRef {
+    /// Creates a reference to the value at the given index, leaving the stack untouched.
+    ///
+    /// # Safety
+    ///
+    /// `lua` must be a valid state with a value at `index`.
+    pub unsafe fn new(lua: *mut lua_State, index: i32) -> LuaRef {
+        let closed = state::state_data(lua).closed.clone();
+        td_clua::lua_pushvalue(lua, index);
+        LuaRef {
+            lua: main_thread(lua),
+            reference: td_clua::luaL_ref(lua, td_clua::LUA_REGISTRYINDEX),
+            closed,
+        }
+    }
+
+    /// Returns true once the state holding the value is closed.
+    pub fn is_closed(&self) -> bool {
+        self.closed.get()
+    }
+
+    /// Pushes the referenced value on the top of the stack of `lua`.
+    ///
+    /// Panics if the state holding the value is closed.
+    pub(crate) fn push(&self, lua: *mut lua_State) {
+        assert!(!self.is_closed(), "the Lua state holding the reference is closed");
+        unsafe {
+            td_clua::lua_rawgeti(lua, td_clua::LUA_REGISTRYINDEX, self.reference as td_clua::lua_Integer);
+        }
+    }
+
+    /// Returns the main thread of the state holding the value.
+    pub fn state(&self) -> *mut lua_State {
+        self.lua
+    }
+
+    /// Returns the type of the referenced value, as given by `lua_type`, or `LUA_TNONE`
+    /// once the state is closed.
+    pub fn lua_type(&self) -> i32 {
+        if self.is_closed() {
+            return td_clua::LUA_TNONE;
+        }
+        self.push(self.lua);
+        unsafe {
+            let tp = td_clua::lua_type(self.lua, -1);
+            td_clua::lua_pop(self.lua, 1);
+            tp
+        }
+    }
+}
+
+/// Error returned when a reference is used after its state was closed.
+pub(crate) fn closed_error() -> LuaError {
+    LuaError::Runtime {
+        message: "the Lua state holding the reference is closed".to_string(),
+        traceback: None,
+        frames: Vec::new(),
+    }
+}
+
+impl LuaRead for LuaRef {
+    fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<LuaRef> {
+        Some(unsafe { LuaRef::new(lua, index) })
+    }
+}
+
+impl LuaPush for LuaRef {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        self.push(lua);
+        1
+    }
+}
+
+impl LuaPush for &LuaRef {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        self.push(lua);
+        1
+    }
+}
+
+impl Clone for LuaRef {
+    fn clone(&self) -> LuaRef {
+        let reference = if self.is_closed() {
+            td_clua::LUA_NOREF
+        } else {
+            self.push(self.lua);
+            unsafe { td_clua::luaL_ref(self.lua, td_clua::LUA_REGISTRYINDEX) }
+        };
+        LuaRef {
+            lua: self.lua,
+            reference,
+            closed: self.closed.clone(),
+        }
+    }
+}
+
+impl PartialEq for LuaRef {
+    /// Two references are equal when they designate the same Lua value, which never holds
+    /// once the state is closed.
+    fn eq(&self, other: &LuaRef) -> bool {
+        if self.lua != other.lua || self.is_closed() {
+            return false;
+        }
+        self.push(self.lua);
+        other.push(self.lua);
+        unsafe {
+            let equal = td_clua::lua_rawequal(self.lua, -1, -2) != 0;
+            td_clua::lua_pop(self.lua, 2);
+            equal
+        }
+    }
+}
+
+impl fmt::Debug for LuaRef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "LuaRef({})", self.reference)
+    }
+}
+
+impl Drop for LuaRef {
+    fn drop(&mut self) {
+        if self.is_closed() {
+            return;
+        }
+        unsafe { td_clua::luaL_unref(self.lua, td_clua::LUA_REGISTRYINDEX, self.reference) }
+    }
+}
+
+/// Reference to a Lua function, which can be stored on the Rust side and called later,
+/// for example to keep the callbacks of an event system.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LuaFunctionRef(LuaRef);
+
+impl LuaFunctionRef {
+    /// Calls the function and reads the values it returns, like `Lua::call`.
+    ///
+    /// Fails once the state holding the function is closed.
+    pub fn call<R>(&self, args: impl LuaPush) -> LuaResult<R> where R: LuaRead {
+        if self.0.is_closed() {
+            return Err(closed_error());
+        }
+        let lua = self.0.state();
+        let top = unsafe { td_clua::lua_gettop(lua) };
+        self.0.push(lua);
+        let nargs = args.push_to_lua(lua);
+        ::pcall(lua, nargs, td_clua::MULTRET)?;
+        let count = unsafe { td_clua::lua_gettop(lua) } - top;
+        ::read_results(lua, count)
+    }
+
+    /// Returns the untyped reference.
+    pub fn as_lua_ref(&self) -> &LuaRef {
+        &self.0
+    }
+
+    /// Checks that the reference designates a function.
+    pub fn from_lua_ref(reference: LuaRef) -> Option<LuaFunctionRef> {
+        if reference.lua_type() == td_clua::LUA_TFUNCTION {
+            Some(LuaFunctionRef(reference))
+        } else {
+            None
+        }
+    }
+}
+
+impl LuaRead for LuaFunctionRef {
+    fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<LuaFunctionRef> {
+        if unsafe { td_clua::lua_isfunction(lua, index) } {
+            Some(LuaFunctionRef(unsafe { LuaRef::new(lua, index) }))
+        } else {
+            None
+        }
+    }
+
+    fn lua_type_name() -> String {
+        "function".to_string()
+    }
+}
+
+impl LuaPush for LuaFunctionRef {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        self.0.push_to_lua(lua)
+    }
+}
+
+impl LuaPush for &LuaFunctionRef {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        (&self.0).push_to_lua(lua)
+    }
+}
+
+/// Reference to a Lua table, which can be stored on the Rust side.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LuaTableRef(LuaRef);
+
+impl LuaTableRef {
+    /// Creates a new empty table.
+    ///
+    /// # Safety
+    ///
+    /// `lua` must be a valid state.
+    pub unsafe fn new(lua: *mut lua_State) -> LuaTableRef {
+        td_clua::lua_newtable(lua);
+        let table = LuaTableRef(LuaRef::new(lua, -1));
+        td_clua::lua_pop(lua, 1);
+        table
+    }
+
+    /// Loads a value in the table given its key, or `None` once the state is closed.
+    pub fn query<R, K>(&self, key: K) -> Option<R> where R: LuaRead, K: LuaPush {
+        if self.0.is_closed() {
+            return None;
+        }
+        let lua = self.0.state();
+        self.0.push(lua);
+        key.push_to_lua(lua);
+        unsafe {
+            td_clua::lua_gettable(lua, -2);
+            td_clua::lua_remove(lua, -2);
+        }
+        LuaRead::lua_read_with_pop(lua, -1, 1)
+    }
+
+    /// Inserts or modifies an element of the table.
+    ///
+    /// Panics if the state holding the table is closed.
+    pub fn set<K, V>(&self, key: K, value: V) where K: LuaPush, V: LuaPush {
+        let lua = self.0.state();
+        self.0.push(lua);
+        key.push_to_lua(lua);
+        value.push_to_lua(lua);
+        unsafe {
+            td_clua::lua_settable(lua, -3);
+            td_clua::lua_pop(lua, 1);
+        }
+    }
+
+    /// Length of the table, as given by the `#` operator without metamethods.
+    ///
+    /// Panics if the state holding the table is closed.
+    pub fn table_len(&self) -> usize {
+        let lua = self.0.state();
+        self.0.push(lua);
+        unsafe {
+            let len = td_clua::lua_rawlen(lua, -1);
+            td_clua::lua_pop(lua, 1);
+            len
+        }
+    }
+
+    /// Returns the untyped reference.
+    pub fn as_lua_ref(&self) -> &LuaRef {
+        &self.0
+    }
+
+    /// Checks that the reference designates a table.
+    pub fn from_lua_ref(reference: LuaRef) -> Option<LuaTableRef> {
+        if reference.lua_type() == td_clua::LUA_TTABLE {
+            Some(LuaTableRef(reference))
+        } else {
+            None
+        }
+    }
+}
+
+impl LuaRead for LuaTableRef {
+    fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<LuaTableRef> {
+        if unsafe { td_clua::lua_istable(lua, index) } {
+            Some(LuaTableRef(unsafe { LuaRef::new(lua, index) }))
+        } else {
+            None
+        }
+    }
+
+    fn lua_type_name() -> String {
+        "table".to_string()
+    }
+}
+
+impl LuaPush for LuaTableRef {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        self.0.push_to_lua(lua)
+    }
+}
+
+impl LuaPush for &LuaTableRef {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        (&self.0).push_to_lua(lua)
+    }
+}
@@ -0,0 +1,886 @@
+//! Conversion between Rust types implementing serde's traits and Lua values,
+//! available with the `serde` feature.
+//!
+//! Structs and maps become tables with the field names or map keys as keys, sequences and
+//! tuples become arrays starting at 1, byte buffers become Lua strings and enums follow the
+//! externally tagged representation: a unit variant is its name as a string, the other
+//! variants are a table with a single `name = content` pair.
+
+use std::fmt::Display;
+use std::ptr;
+use std::vec;
+
+use libc;
+use serde::de::{self, DeserializeOwned, IntoDeserializer, Unexpected};
+use serde::ser::{self, Serialize};
+use td_clua::{self, lua_State};
+
+use LuaError;
+use LuaPush;
+use LuaRead;
+use LuaResult;
+use LuaValue;
+
+/// How a table is recognized as an array when the Rust type doesn't tell,
+/// e.g. when deserializing into `serde_json::Value` or an untagged enum.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ArrayDetection {
+    /// The keys of the table are exactly the integers `1..n`.
+    Sequence,
+    /// Every key of the table is a positive integer, the holes are read as nil.
+    PositiveIntegers,
+    /// Tables are always read as maps.
+    Never,
+}
+
+/// Options of the conversions.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SerdeOptions {
+    /// Serializes `None` and `()` as the `Null` light userdata instead of nil, so that the key
+    /// of a struct field or map entry still exists in the table and sequences have no holes.
+    pub none_as_null: bool,
+    /// How tables are told apart when deserializing a self-describing type.
+    pub array_detection: ArrayDetection,
+    /// Reads an empty table as an empty array instead of an empty map
+    /// when deserializing a self-describing type.
+    pub empty_table_as_array: bool,
+}
+
+impl Default for SerdeOptions {
+    fn default() -> SerdeOptions {
+        SerdeOptions {
+            none_as_null: false,
+            array_detection: ArrayDetection::Sequence,
+            empty_table_as_array: false,
+        }
+    }
+}
+
+/// The null value, a light userdata holding a null pointer.
+///
+/// It stands for `None` in tables where nil would remove the key, and is read as `None` or `()`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Null;
+
+impl LuaPush for Null {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        unsafe { td_clua::lua_pushlightuserdata(lua, ptr::null_mut()) };
+        1
+    }
+}
+
+/// Wrapper pushing and reading a value through serde, so it can be used wherever a
+/// `LuaPush` or a `LuaRead` is expected:
+///
+/// ```ignore
+/// lua.set("config", Serde(config));
+/// let Serde(config): Serde<Config> = lua.query("config").unwrap();
+/// ```
+///
+/// A value that fails to serialize is pushed as nil, use `to_lua` to get the error.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Serde<T>(pub T);
+
+impl<T> LuaPush for Serde<T> where T: Serialize {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        if to_lua(lua, &self.0).is_err() {
+            unsafe { td_clua::lua_pushnil(lua) };
+        }
+        1
+    }
+}
+
+impl<T> LuaRead for Serde<T> where T: DeserializeOwned {
+    fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<Serde<T>> {
+        from_lua(lua, index).ok().map(Serde)
+    }
+}
+
+/// Pushes the value on the stack, exactly one Lua value is pushed when it succeeds.
+pub fn to_lua<T>(lua: *mut lua_State, value: &T) -> LuaResult<()> where T: Serialize + ?Sized {
+    to_lua_with_options(lua, value, SerdeOptions::default())
+}
+
+pub fn to_lua_with_options<T>(lua: *mut lua_State, value: &T, options: SerdeOptions) -> LuaResult<()>
+    where T: Serialize + ?Sized
+{
+    to_value_with_options(value, options)?.push_to_lua(lua);
+    Ok(())
+}
+
+/// Reads the value at the given index of the stack, leaving the stack untouched.
+pub fn from_lua<T>(lua: *mut lua_State, index: i32) -> LuaResult<T> where T: DeserializeOwned {
+    from_lua_with_options(lua, index, SerdeOptions::default())
+}
+
+pub fn from_lua_with_options<T>(lua: *mut lua_State, index: i32, options: SerdeOptions) -> LuaResult<T>
+    where T: DeserializeOwned
+{
+    match LuaValue::lua_read_at_position(lua, index) {
+        Some(value) => from_value_with_options(value, options),
+        None => Err(LuaError::Serde("cannot read a cyclic or too deeply nested table".to_string())),
+    }
+}
+
+/// Converts the value to a `LuaValue`.
+pub fn to_value<T>(value: &T) -> LuaResult<LuaValue> where T: Serialize + ?Sized {
+    to_value_with_options(value, SerdeOptions::default())
+}
+
+pub fn to_value_with_options<T>(value: &T, options: SerdeOptions) -> LuaResult<LuaValue>
+    where T: Serialize + ?Sized
+{
+    value.serialize(Serializer { options })
+}
+
+/// Builds a Rust value from a `LuaValue`.
+pub fn from_value<T>(value: LuaValue) -> LuaResult<T> where T: DeserializeOwned {
+    from_value_with_options(value, SerdeOptions::default())
+}
+
+pub fn from_value_with_options<T>(value: LuaValue, options: SerdeOptions) -> LuaResult<T>
+    where T: DeserializeOwned
+{
+    T::deserialize(Deserializer { value, options })
+}
+
+impl ser::Error for LuaError {
+    fn custom<T: Display>(msg: T) -> LuaError {
+        LuaError::Serde(msg.to_string())
+    }
+}
+
+impl de::Error for LuaError {
+    fn custom<T: Display>(msg: T) -> LuaError {
+        LuaError::Serde(msg.to_string())
+    }
+
+    fn invalid_type(unexp: Unexpected, exp: &dyn de::Expected) -> LuaError {
+        LuaError::Conversion {
+            expected: exp.to_string(),
+            actual: unexp.to_string(),
+        }
+    }
+}
+
+fn is_null(value: &LuaValue) -> bool {
+    match *value {
+        LuaValue::Nil => true,
+        LuaValue::LightUserdata(p) => p.is_null(),
+        _ => false,
+    }
+}
+
+fn unexpected(value: &LuaValue) -> Unexpected<'_> {
+    match *value {
+        LuaValue::Nil => Unexpected::Unit,
+        LuaValue::Boolean(b) => Unexpected::Bool(b),
+        LuaValue::Integer(i) => Unexpected::Signed(i),
+        LuaValue::Number(n) => Unexpected::Float(n),
+        LuaValue::String(ref bytes) => match ::std::str::from_utf8(bytes) {
+            Ok(s) => Unexpected::Str(s),
+            Err(_) => Unexpected::Bytes(bytes),
+        },
+        LuaValue::Table(_) => Unexpected::Map,
+        ref other => Unexpected::Other(other.type_name()),
+    }
+}
+
+/// Returns the values of the table in key order if every key is a positive integer.
+/// Holes are filled with nil when allowed, as long as they don't make up most of the array.
+fn array_items(pairs: &[(LuaValue, LuaValue)], allow_holes: bool) -> Option<Vec<LuaValue>> {
+    let mut len = 0;
+    for (key, _) in pairs {
+        match key.as_integer() {
+            Some(i) if i >= 1 => len = len.max(i as usize),
+            _ => return None,
+        }
+    }
+    if len != pairs.len() && (!allow_holes || len > pairs.len() * 2) {
+        return None;
+    }
+    let mut items = vec![LuaValue::Nil; len];
+    for (key, value) in pairs {
+        items[key.as_integer().unwrap() as usize - 1] = value.clone();
+    }
+    Some(items)
+}
+
+fn array_value(items: Vec<LuaValue>) -> LuaValue {
+    LuaValue::Table(items.into_iter()
+        .enumerate()
+        .map(|(i, value)| (LuaValue::Integer(i as i64 + 1), value))
+        .collect())
+}
+
+struct Serializer {
+    options: SerdeOptions,
+}
+
+impl Serializer {
+    fn none(&self) -> LuaValue {
+        if self.options.none_as_null {
+            LuaValue::LightUserdata(ptr::null_mut::<libc::c_void>())
+        } else {
+            LuaValue::Nil
+        }
+    }
+}
+
+impl ser::Serializer for Serializer {
+    type Ok = LuaValue;
+    type Error = LuaError;
+    type SerializeSeq = SerializeVec;
+    type SerializeTuple = SerializeVec;
+    type SerializeTupleStruct = SerializeVec;
+    type SerializeTupleVariant = SerializeTupleVariant;
+    type SerializeMap = SerializeMap;
+    type SerializeStruct = SerializeMap;
+    type SerializeStructVariant = SerializeStructVariant;
+
+    fn serialize_bool(self, v: bool) -> LuaResult<LuaValue> {
+        Ok(LuaValue::Boolean(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> LuaResult<LuaValue> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i16(self, v: i16) -> LuaResult<LuaValue> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i32(self, v: i32) -> LuaResult<LuaValue> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_i64(self, v: i64) -> LuaResult<LuaValue> {
+        Ok(LuaValue::Integer(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> LuaResult<LuaValue> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u16(self, v: u16) -> LuaResult<LuaValue> {
+        self.serialize_i64(v as i64)
+    }
+
+    fn serialize_u32(self, v: u32) -> LuaResult<LuaValue> {
+        self.serialize_i64(v as i64)
+    }
+
+    /// Integers too large for a Lua integer become floats.
+    fn serialize_u64(self, v: u64) -> LuaResult<LuaValue> {
+        if v > i64::MAX as u64 {
+            Ok(LuaValue::Number(v as f64))
+        } else {
+            Ok(LuaValue::Integer(v as i64))
+        }
+    }
+
+    fn serialize_f32(self, v: f32) -> LuaResult<LuaValue> {
+        Ok(LuaValue::Number(v as f64))
+    }
+
+    fn serialize_f64(self, v: f64) -> LuaResult<LuaValue> {
+        Ok(LuaValue::Number(v))
+    }
+
+    fn serialize_char(self, v: char) -> LuaResult<LuaValue> {
+        Ok(LuaValue::from(v.to_string()))
+    }
+
+    fn serialize_str(self, v: &str) -> LuaResult<LuaValue> {
+        Ok(LuaValue::from(v))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> LuaResult<LuaValue> {
+        Ok(LuaValue::String(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> LuaResult<LuaValue> {
+        Ok(self.none())
+    }
+
+    fn serialize_some<T>(self, value: &T) -> LuaResult<LuaValue> where T: Serialize + ?Sized {
+        value.serialize(self)
+    }
+
+    fn serialize_unit(self) -> LuaResult<LuaValue> {
+        Ok(self.none())
+    }
+
+    fn serialize_unit_struct(self, _name: &'static str) -> LuaResult<LuaValue> {
+        Ok(self.none())
+    }
+
+    fn serialize_unit_variant(self, _name: &'static str, _index: u32, variant: &'static str)
+        -> LuaResult<LuaValue>
+    {
+        Ok(LuaValue::from(variant))
+    }
+
+    fn serialize_newtype_struct<T>(self, _name: &'static str, value: &T) -> LuaResult<LuaValue>
+        where T: Serialize + ?Sized
+    {
+        value.serialize(self)
+    }
+
+    fn serialize_newtype_variant<T>(self, _name: &'static str, _index: u32, variant: &'static str, value: &T)
+        -> LuaResult<LuaValue>
+        where T: Serialize + ?Sized
+    {
+        let value = value.serialize(self)?;
+        Ok(LuaValue::Table(vec![(LuaValue::from(variant), value)]))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> LuaResult<SerializeVec> {
+        Ok(SerializeVec {
+            options: self.options,
+            items: Vec::with_capacity(len.unwrap_or(0)),
+        })
+    }
+
+    fn serialize_tuple(self, len: usize) -> LuaResult<SerializeVec> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_struct(self, _name: &'static str, len: usize) -> LuaResult<SerializeVec> {
+        self.serialize_seq(Some(len))
+    }
+
+    fn serialize_tuple_variant(self, _name: &'static str, _index: u32, variant: &'static str, len: usize)
+        -> LuaResult<SerializeTupleVariant>
+    {
+        Ok(SerializeTupleVariant {
+            variant,
+            vec: self.serialize_seq(Some(len))?,
+        })
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> LuaResult<SerializeMap> {
+        Ok(SerializeMap {
+            options: self.options,
+            pairs: Vec::with_capacity(len.unwrap_or(0)),
+            next_key: None,
+        })
+    }
+
+    fn serialize_struct(self, _name: &'static str, len: usize) -> LuaResult<SerializeMap> {
+        self.serialize_map(Some(len))
+    }
+
+    fn serialize_struct_variant(self, _name: &'static str, _index: u32, variant: &'static str, len: usize)
+        -> LuaResult<SerializeStructVariant>
+    {
+        Ok(SerializeStructVariant {
+            variant,
+            map: self.serialize_map(Some(len))?,
+        })
+    }
+}
+
+struct SerializeVec {
+    options: SerdeOptions,
+    items: Vec<LuaValue>,
+}
+
+impl ser::SerializeSeq for SerializeVec {
+    type Ok = LuaValue;
+    type Error = LuaError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> LuaResult<()> where T: Serialize + ?Sized {
+        let value = value.serialize(Serializer { options: self.options })?;
+        self.items.push(value);
+        Ok(())
+    }
+
+    fn end(self) -> LuaResult<LuaValue> {
+        Ok(array_value(self.items))
+    }
+}
+
+impl ser::SerializeTuple for SerializeVec {
+    type Ok = LuaValue;
+    type Error = LuaError;
+
+    fn serialize_element<T>(&mut self, value: &T) -> LuaResult<()> where T: Serialize + ?Sized {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> LuaResult<LuaValue> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+impl ser::SerializeTupleStruct for SerializeVec {
+    type Ok = LuaValue;
+    type Error = LuaError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> LuaResult<()> where T: Serialize + ?Sized {
+        ser::SerializeSeq::serialize_element(self, value)
+    }
+
+    fn end(self) -> LuaResult<LuaValue> {
+        ser::SerializeSeq::end(self)
+    }
+}
+
+struct SerializeTupleVariant {
+    variant: &'static str,
+    vec: SerializeVec,
+}
+
+impl ser::SerializeTupleVariant for SerializeTupleVariant {
+    type Ok = LuaValue;
+    type Error = LuaError;
+
+    fn serialize_field<T>(&mut self, value: &T) -> LuaResult<()> where T: Serialize + ?Sized {
+        ser::SerializeSeq::serialize_element(&mut self.vec, value)
+    }
+
+    fn end(self) -> LuaResult<LuaValue> {
+        let value = ser::SerializeSeq::end(self.vec)?;
+        Ok(LuaValue::Table(vec![(LuaValue::from(self.variant), value)]))
+    }
+}
+
+struct SerializeMap {
+    options: SerdeOptions,
+    pairs: Vec<(LuaValue, LuaValue)>,
+    next_key: Option<LuaValue>,
+}
+
+impl ser::SerializeMap for SerializeMap {
+    type Ok = LuaValue;
+    type Error = LuaError;
+
+    fn serialize_key<T>(&mut self, key: &T) -> LuaResult<()> where T: Serialize + ?Sized {
+        let key = key.serialize(Serializer { options: self.options })?;
+        if key.is_nil() {
+            return Err(LuaError::Serde("map key can't be nil".to_string()));
+        }
+        self.next_key = Some(key);
+        Ok(())
+    }
+
+    fn serialize_value<T>(&mut self, value: &T) -> LuaResult<()> where T: Serialize + ?Sized {
+        let key = match self.next_key.take() {
+            Some(key) => key,
+            None => return Err(LuaError::Serde("map value serialized before its key".to_string())),
+        };
+        let value = value.serialize(Serializer { options: self.options })?;
+        self.pairs.push((key, value));
+        Ok(())
+    }
+
+    fn end(self) -> LuaResult<LuaValue> {
+        Ok(LuaValue::Table(self.pairs))
+    }
+}
+
+impl ser::SerializeStruct for SerializeMap {
+    type Ok = LuaValue;
+    type Error = LuaError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> LuaResult<()>
+        where T: Serialize + ?Sized
+    {
+        let value = value.serialize(Serializer { options: self.options })?;
+        self.pairs.push((LuaValue::from(key), value));
+        Ok(())
+    }
+
+    fn end(self) -> LuaResult<LuaValue> {
+        Ok(LuaValue::Table(self.pairs))
+    }
+}
+
+struct SerializeStructVariant {
+    variant: &'static str,
+    map: SerializeMap,
+}
+
+impl ser::SerializeStructVariant for SerializeStructVariant {
+    type Ok = LuaValue;
+    type Error = LuaError;
+
+    fn serialize_field<T>(&mut self, key: &'static str, value: &T) -> LuaResult<()>
+        where T: Serialize + ?Sized
+    {
+        ser::SerializeStruct::serialize_field(&mut self.map, key, value)
+    }
+
+    fn end(self) -> LuaResult<LuaValue> {
+        let value = ser::SerializeStruct::end(self.map)?;
+        Ok(LuaValue::Table(vec![(LuaValue::from(self.variant), value)]))
+    }
+}
+
+/// Tells whether a table is read as an array by `deserialize_any`.
+fn is_array(pairs: &[(LuaValue, LuaValue)], options: SerdeOptions) -> bool {
+    if pairs.is_empty() {
+        return options.empty_table_as_array;
+    }
+    match options.array_detection {
+        ArrayDetection::Sequence => array_items(pairs, false).is_some(),
+        ArrayDetection::PositiveIntegers => array_items(pairs, true).is_some(),
+        ArrayDetection::Never => false,
+    }
+}
+
+fn visit_array<'de, V>(items: Vec<LuaValue>, options: SerdeOptions, visitor: V) -> LuaResult<V::Value>
+    where V: de::Visitor<'de>
+{
+    let len = items.len();
+    let mut seq = SeqDeserializer {
+        iter: items.into_iter(),
+        options,
+    };
+    let value = visitor.visit_seq(&mut seq)?;
+    if seq.iter.len() != 0 {
+        return Err(de::Error::invalid_length(len, &"fewer elements in the table"));
+    }
+    Ok(value)
+}
+
+fn visit_table<'de, V>(pairs: Vec<(LuaValue, LuaValue)>, options: SerdeOptions, visitor: V) -> LuaResult<V::Value>
+    where V: de::Visitor<'de>
+{
+    visitor.visit_map(MapDeserializer {
+        iter: pairs.into_iter(),
+        value: None,
+        options,
+    })
+}
+
+fn variant_name(value: LuaValue) -> LuaResult<String> {
+    match value {
+        LuaValue::String(bytes) => String::from_utf8(bytes)
+            .map_err(|err| de::Error::invalid_value(Unexpected::Bytes(err.as_bytes()), &"a variant name")),
+        value => Err(de::Error::invalid_type(unexpected(&value), &"a variant name")),
+    }
+}
+
+struct Deserializer {
+    value: LuaValue,
+    options: SerdeOptions,
+}
+
+impl Deserializer {
+    fn new(value: LuaValue, options: SerdeOptions) -> Deserializer {
+        Deserializer { value, options }
+    }
+
+    fn invalid_type<V>(&self, exp: &dyn de::Expected) -> LuaResult<V> {
+        Err(de::Error::invalid_type(unexpected(&self.value), exp))
+    }
+
+
+    fn deserialize_integer<'de, V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        match self.value {
+            LuaValue::Integer(i) => visitor.visit_i64(i),
+            LuaValue::Number(n) if n.fract() == 0.0 && n >= i64::MIN as f64 && n < i64::MAX as f64 => {
+                visitor.visit_i64(n as i64)
+            }
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_float<'de, V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        match self.value {
+            LuaValue::Integer(i) => visitor.visit_f64(i as f64),
+            LuaValue::Number(n) => visitor.visit_f64(n),
+            _ => self.invalid_type(&visitor),
+        }
+    }
+}
+
+macro_rules! deserialize_integers {
+    ($($method:ident)*) => ($(
+        fn $method<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+            self.deserialize_integer(visitor)
+        }
+    )*)
+}
+
+impl<'de> de::Deserializer<'de> for Deserializer {
+    type Error = LuaError;
+
+    fn deserialize_any<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        if is_null(&self.value) {
+            return visitor.visit_unit();
+        }
+        match self.value {
+            LuaValue::Boolean(b) => visitor.visit_bool(b),
+            LuaValue::Integer(i) => visitor.visit_i64(i),
+            LuaValue::Number(n) => visitor.visit_f64(n),
+            LuaValue::String(bytes) => match String::from_utf8(bytes) {
+                Ok(s) => visitor.visit_string(s),
+                Err(err) => visitor.visit_byte_buf(err.into_bytes()),
+            },
+            LuaValue::Table(pairs) => {
+                if is_array(&pairs, self.options) {
+                    let items = array_items(&pairs, true).unwrap_or_default();
+                    visit_array(items, self.options, visitor)
+                } else {
+                    visit_table(pairs, self.options, visitor)
+                }
+            }
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    deserialize_integers!(deserialize_i8 deserialize_i16 deserialize_i32 deserialize_i64
+                          deserialize_u8 deserialize_u16 deserialize_u32 deserialize_u64);
+
+    fn deserialize_f32<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        self.deserialize_float(visitor)
+    }
+
+    fn deserialize_f64<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        self.deserialize_float(visitor)
+    }
+
+    /// Numbers are accepted and converted like Lua does, which allows integer keys
+    /// to be read as the keys of a map indexed by strings.
+    fn deserialize_str<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        match self.value {
+            LuaValue::String(bytes) => match String::from_utf8(bytes) {
+                Ok(s) => visitor.visit_string(s),
+                Err(err) => Err(de::Error::invalid_value(Unexpected::Bytes(err.as_bytes()), &visitor)),
+            },
+            LuaValue::Integer(i) => visitor.visit_string(i.to_string()),
+            LuaValue::Number(n) => visitor.visit_string(n.to_string()),
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_string<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_char<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_bytes<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        if let LuaValue::Table(_) = self.value {
+            return self.deserialize_seq(visitor);
+        }
+        match self.value {
+            LuaValue::String(bytes) => visitor.visit_byte_buf(bytes),
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_byte_buf<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        self.deserialize_bytes(visitor)
+    }
+
+    fn deserialize_option<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        if is_null(&self.value) {
+            visitor.visit_none()
+        } else {
+            visitor.visit_some(self)
+        }
+    }
+
+    fn deserialize_unit<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        if is_null(&self.value) {
+            visitor.visit_unit()
+        } else {
+            self.invalid_type(&visitor)
+        }
+    }
+
+    fn deserialize_unit_struct<V>(self, _name: &'static str, visitor: V) -> LuaResult<V::Value>
+        where V: de::Visitor<'de>
+    {
+        self.deserialize_unit(visitor)
+    }
+
+    fn deserialize_newtype_struct<V>(self, _name: &'static str, visitor: V) -> LuaResult<V::Value>
+        where V: de::Visitor<'de>
+    {
+        visitor.visit_newtype_struct(self)
+    }
+
+    /// Every key of the table must be a positive integer, holes are read as nil.
+    fn deserialize_seq<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        let items = match self.value {
+            LuaValue::Table(ref pairs) => array_items(pairs, true),
+            _ => None,
+        };
+        match items {
+            Some(items) => visit_array(items, self.options, visitor),
+            None => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_tuple<V>(self, _len: usize, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_tuple_struct<V>(self, _name: &'static str, _len: usize, visitor: V) -> LuaResult<V::Value>
+        where V: de::Visitor<'de>
+    {
+        self.deserialize_seq(visitor)
+    }
+
+    fn deserialize_map<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        match self.value {
+            LuaValue::Table(pairs) => visit_table(pairs, self.options, visitor),
+            _ => self.invalid_type(&visitor),
+        }
+    }
+
+    fn deserialize_struct<V>(self, _name: &'static str, _fields: &'static [&'static str], visitor: V)
+        -> LuaResult<V::Value>
+        where V: de::Visitor<'de>
+    {
+        self.deserialize_map(visitor)
+    }
+
+    fn deserialize_enum<V>(self, _name: &'static str, _variants: &'static [&'static str], visitor: V)
+        -> LuaResult<V::Value>
+        where V: de::Visitor<'de>
+    {
+        let options = self.options;
+        let (variant, value) = match self.value {
+            LuaValue::Table(mut pairs) => {
+                if pairs.len() != 1 {
+                    return Err(de::Error::invalid_length(pairs.len(), &"a table with a single key"));
+                }
+                let (key, value) = pairs.pop().unwrap();
+                (variant_name(key)?, Some(value))
+            }
+            value => (variant_name(value)?, None),
+        };
+        visitor.visit_enum(EnumDeserializer { variant, value, options })
+    }
+
+    fn deserialize_identifier<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        self.deserialize_str(visitor)
+    }
+
+    fn deserialize_ignored_any<V>(self, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        visitor.visit_unit()
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i128 u128
+    }
+}
+
+struct SeqDeserializer {
+    iter: vec::IntoIter<LuaValue>,
+    options: SerdeOptions,
+}
+
+impl<'de> de::SeqAccess<'de> for SeqDeserializer {
+    type Error = LuaError;
+
+    fn next_element_seed<T>(&mut self, seed: T) -> LuaResult<Option<T::Value>> where T: de::DeserializeSeed<'de> {
+        match self.iter.next() {
+            Some(value) => seed.deserialize(Deserializer::new(value, self.options)).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct MapDeserializer {
+    iter: vec::IntoIter<(LuaValue, LuaValue)>,
+    value: Option<LuaValue>,
+    options: SerdeOptions,
+}
+
+impl<'de> de::MapAccess<'de> for MapDeserializer {
+    type Error = LuaError;
+
+    fn next_key_seed<K>(&mut self, seed: K) -> LuaResult<Option<K::Value>> where K: de::DeserializeSeed<'de> {
+        match self.iter.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(Deserializer::new(key, self.options)).map(Some)
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V>(&mut self, seed: V) -> LuaResult<V::Value> where V: de::DeserializeSeed<'de> {
+        let value = self.value.take().unwrap_or(LuaValue::Nil);
+        seed.deserialize(Deserializer::new(value, self.options))
+    }
+
+    fn size_hint(&self) -> Option<usize> {
+        Some(self.iter.len())
+    }
+}
+
+struct EnumDeserializer {
+    variant: String,
+    value: Option<LuaValue>,
+    options: SerdeOptions,
+}
+
+impl<'de> de::EnumAccess<'de> for EnumDeserializer {
+    type Error = LuaError;
+    type Variant = VariantDeserializer;
+
+    fn variant_seed<V>(self, seed: V) -> LuaResult<(V::Value, VariantDeserializer)>
+        where V: de::DeserializeSeed<'de>
+    {
+        let variant = seed.deserialize(self.variant.into_deserializer())?;
+        Ok((variant, VariantDeserializer { value: self.value, options: self.options }))
+    }
+}
+
+struct VariantDeserializer {
+    value: Option<LuaValue>,
+    options: SerdeOptions,
+}
+
+impl VariantDeserializer {
+    fn content(self, exp: &dyn de::Expected) -> LuaResult<Deserializer> {
+        match self.value {
+            Some(value) => Ok(Deserializer::new(value, self.options)),
+            None => Err(de::Error::invalid_type(Unexpected::UnitVariant, exp)),
+        }
+    }
+}
+
+impl<'de> de::VariantAccess<'de> for VariantDeserializer {
+    type Error = LuaError;
+
+    fn unit_variant(self) -> LuaResult<()> {
+        match self.value {
+            Some(ref value) if !is_null(value) => {
+                Err(de::Error::invalid_type(unexpected(value), &"unit variant"))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn newtype_variant_seed<T>(self, seed: T) -> LuaResult<T::Value> where T: de::DeserializeSeed<'de> {
+        let content = self.content(&"newtype variant")?;
+        seed.deserialize(content)
+    }
+
+    fn tuple_variant<V>(self, _len: usize, visitor: V) -> LuaResult<V::Value> where V: de::Visitor<'de> {
+        let content = self.content(&"tuple variant")?;
+        de::Deserializer::deserialize_seq(content, visitor)
+    }
+
+    fn struct_variant<V>(self, _fields: &'static [&'static str], visitor: V) -> LuaResult<V::Value>
+        where V: de::Visitor<'de>
+    {
+        let content = self.content(&"struct variant")?;
+        de::Deserializer::deserialize_map(content, visitor)
+    }
+}
@@ -26,6 +26,10 @@ impl LuaRead for LuaTable {
             None
         }
     }
+
+    fn lua_type_name() -> String {
+        "table".to_string()
+    }
 }
 
 impl Drop for LuaTable {
@@ -0,0 +1,182 @@
+use td_clua::{self, lua_State};
+
+use error;
+use limits;
+use lua_ref;
+use panic;
+use LuaError;
+use LuaFunctionRef;
+use LuaPush;
+use LuaRead;
+use LuaRef;
+use LuaResult;
+
+/// State of a coroutine, as returned by `coroutine.status` in Lua.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThreadStatus {
+    /// Not started yet or suspended by a yield, it can be resumed.
+    Suspended,
+    /// Currently running, or waiting for a coroutine it resumed.
+    Running,
+    /// The body function returned.
+    Finished,
+    /// The coroutine stopped because of an error.
+    Error,
+}
+
+/// Values received from a coroutine by `LuaThread::resume`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Resume<R> {
+    /// The values passed to `coroutine.yield`, the coroutine can be resumed again.
+    Yield(R),
+    /// The values returned by the body function.
+    Finished(R),
+}
+
+impl<R> Resume<R> {
+    /// Returns the values, whether they were yielded or returned.
+    pub fn into_inner(self) -> R {
+        match self {
+            Resume::Yield(values) | Resume::Finished(values) => values,
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match *self {
+            Resume::Finished(_) => true,
+            Resume::Yield(_) => false,
+        }
+    }
+}
+
+/// A Lua coroutine driven from Rust.
+///
+/// It is created from a Lua function, or read from a coroutine made by `coroutine.create`,
+/// and stays alive until dropped.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LuaThread {
+    thread: *mut lua_State,
+    reference: LuaRef,
+}
+
+impl LuaThread {
+    /// Creates a coroutine running the function.
+    pub fn new(function: &LuaFunctionRef) -> LuaThread {
+        let lua = function.as_lua_ref().state();
+        unsafe {
+            let thread = td_clua::lua_newthread(lua);
+            let reference = LuaRef::new(lua, -1);
+            td_clua::lua_pop(lua, 1);
+            function.as_lua_ref().push(thread);
+            LuaThread { thread, reference }
+        }
+    }
+
+    /// Returns the state of the coroutine itself.
+    pub fn state(&self) -> *mut lua_State {
+        self.thread
+    }
+
+    /// Returns the state of the coroutine, `Error` once the Lua context is closed.
+    pub fn status(&self) -> ThreadStatus {
+        if self.reference.is_closed() {
+            return ThreadStatus::Error;
+        }
+        unsafe {
+            match td_clua::lua_status(self.thread) {
+                td_clua::LUA_YIELD => ThreadStatus::Suspended,
+                td_clua::LUA_OK => {
+                    let mut ar = td_clua::lua_Debug::default();
+                    if td_clua::lua_getstack(self.thread, 0, &mut ar) > 0 {
+                        ThreadStatus::Running
+                    } else if td_clua::lua_gettop(self.thread) == 0 {
+                        ThreadStatus::Finished
+                    } else {
+                        ThreadStatus::Suspended
+                    }
+                }
+                _ => ThreadStatus::Error,
+            }
+        }
+    }
+
+    /// Starts or continues the coroutine, the arguments being received by the body function
+    /// the first time and returned by `coroutine.yield` afterwards.
+    ///
+    /// An error raised by the coroutine is returned with the traceback of the coroutine and
+    /// kills it. Resuming fails once the Lua context is closed.
+    pub fn resume<R>(&mut self, args: impl LuaPush) -> LuaResult<Resume<R>> where R: LuaRead {
+        if self.reference.is_closed() {
+            return Err(lua_ref::closed_error());
+        }
+        let nargs = args.push_to_lua(self.thread);
+        match self.resume_pushed(nargs)? {
+            Resume::Yield(count) => ::read_results(self.thread, count).map(Resume::Yield),
+            Resume::Finished(count) => ::read_results(self.thread, count).map(Resume::Finished),
+        }
+    }
+
+    /// Resumes the coroutine with the `nargs` values on the top of its stack, and returns
+    /// how many values it yielded or returned, left on its stack.
+    pub(crate) fn resume_pushed(&mut self, nargs: i32) -> LuaResult<Resume<i32>> {
+        if self.reference.is_closed() {
+            return Err(lua_ref::closed_error());
+        }
+        let lua = self.reference.state();
+        let status = self.status();
+        if status != ThreadStatus::Suspended {
+            unsafe { td_clua::lua_pop(self.thread, nargs) };
+            let message = match status {
+                ThreadStatus::Running => "cannot resume non-suspended coroutine",
+                _ => "cannot resume dead coroutine",
+            };
+            return Err(LuaError::Runtime {
+                message: message.to_string(),
+                traceback: None,
+                frames: Vec::new(),
+            });
+        }
+
+        limits::refresh_hook(self.thread);
+        match unsafe { td_clua::lua_resume(self.thread, lua, nargs) } {
+            td_clua::LUA_YIELD => Ok(Resume::Yield(unsafe { td_clua::lua_gettop(self.thread) })),
+            td_clua::LUA_OK => Ok(Resume::Finished(unsafe { td_clua::lua_gettop(self.thread) })),
+            status => {
+                let message = error::error_message(self.thread, -1);
+                error::report_error(lua, self.thread, &message, 0);
+                let err = LuaError::pop_from_stack(self.thread, status);
+                panic::resume_panic(lua, &err);
+                Err(err)
+            }
+        }
+    }
+}
+
+impl LuaRead for LuaThread {
+    fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<LuaThread> {
+        let thread = unsafe { td_clua::lua_tothread(lua, index) };
+        if thread.is_null() {
+            return None;
+        }
+        Some(LuaThread {
+            thread,
+            reference: unsafe { LuaRef::new(lua, index) },
+        })
+    }
+
+    fn lua_type_name() -> String {
+        "thread".to_string()
+    }
+}
+
+impl LuaPush for LuaThread {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        self.reference.push_to_lua(lua)
+    }
+}
+
+impl LuaPush for &LuaThread {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        (&self.reference).push_to_lua(lua)
+    }
+}
@@ -0,0 +1,240 @@
+use std::collections::HashSet;
+
+use libc;
+use td_clua::{self, lua_State};
+
+use LuaPush;
+use LuaRead;
+use LuaRef;
+use LuaFunctionRef;
+use functions;
+
+/// Owned copy of any Lua value.
+///
+/// Plain values and tables are copied to the Rust side, tables as the list of their
+/// key/value pairs. Functions, full userdata and threads are kept alive in the registry
+/// through a reference, so pushing the value back gives the very same Lua object.
+#[derive(Debug, Clone, PartialEq)]
+pub enum LuaValue {
+    Nil,
+    Boolean(bool),
+    Integer(i64),
+    Number(f64),
+    String(Vec<u8>),
+    Table(Vec<(LuaValue, LuaValue)>),
+    Function(LuaFunctionRef),
+    Userdata(LuaRef),
+    LightUserdata(*mut libc::c_void),
+    Thread(LuaRef),
+}
+
+impl LuaValue {
+    /// Name of the Lua type of the value, as returned by the `type` function in Lua.
+    pub fn type_name(&self) -> &'static str {
+        match *self {
+            LuaValue::Nil => "nil",
+            LuaValue::Boolean(_) => "boolean",
+            LuaValue::Integer(_) | LuaValue::Number(_) => "number",
+            LuaValue::String(_) => "string",
+            LuaValue::Table(_) => "table",
+            LuaValue::Function(_) => "function",
+            LuaValue::Userdata(_) | LuaValue::LightUserdata(_) => "userdata",
+            LuaValue::Thread(_) => "thread",
+        }
+    }
+
+    pub fn is_nil(&self) -> bool {
+        *self == LuaValue::Nil
+    }
+
+    pub fn as_bool(&self) -> Option<bool> {
+        match *self {
+            LuaValue::Boolean(b) => Some(b),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as an integer, accepting floats with an exact integer representation.
+    pub fn as_integer(&self) -> Option<i64> {
+        match *self {
+            LuaValue::Integer(i) => Some(i),
+            LuaValue::Number(n) if n.fract() == 0.0 => Some(n as i64),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<f64> {
+        match *self {
+            LuaValue::Integer(i) => Some(i as f64),
+            LuaValue::Number(n) => Some(n),
+            _ => None,
+        }
+    }
+
+    /// Returns the value as a string if it is a valid utf-8 Lua string.
+    pub fn as_str(&self) -> Option<&str> {
+        match *self {
+            LuaValue::String(ref bytes) => std::str::from_utf8(bytes).ok(),
+            _ => None,
+        }
+    }
+
+    /// Looks up a key in a table value, comparing keys with Lua semantics for numbers.
+    pub fn get<K>(&self, key: K) -> Option<&LuaValue> where K: Into<LuaValue> {
+        let key = key.into();
+        match *self {
+            LuaValue::Table(ref pairs) => pairs.iter().find(|(k, _)| same_key(k, &key)).map(|(_, v)| v),
+            _ => None,
+        }
+    }
+}
+
+// in Lua 1 and 1.0 are the same table key
+fn same_key(a: &LuaValue, b: &LuaValue) -> bool {
+    match (a.as_number(), b.as_number()) {
+        (Some(a), Some(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+impl From<bool> for LuaValue {
+    fn from(b: bool) -> LuaValue {
+        LuaValue::Boolean(b)
+    }
+}
+
+impl From<i64> for LuaValue {
+    fn from(i: i64) -> LuaValue {
+        LuaValue::Integer(i)
+    }
+}
+
+impl From<i32> for LuaValue {
+    fn from(i: i32) -> LuaValue {
+        LuaValue::Integer(i as i64)
+    }
+}
+
+impl From<f64> for LuaValue {
+    fn from(n: f64) -> LuaValue {
+        LuaValue::Number(n)
+    }
+}
+
+impl<'s> From<&'s str> for LuaValue {
+    fn from(s: &'s str) -> LuaValue {
+        LuaValue::String(s.as_bytes().to_vec())
+    }
+}
+
+impl From<String> for LuaValue {
+    fn from(s: String) -> LuaValue {
+        LuaValue::String(s.into_bytes())
+    }
+}
+
+impl LuaPush for LuaValue {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        unsafe {
+            match self {
+                LuaValue::Nil => td_clua::lua_pushnil(lua),
+                LuaValue::Boolean(b) => td_clua::lua_pushboolean(lua, b as libc::c_int),
+                LuaValue::Integer(i) => td_clua::lua_pushinteger(lua, i as td_clua::lua_Integer),
+                LuaValue::Number(n) => td_clua::lua_pushnumber(lua, n),
+                LuaValue::String(bytes) => {
+                    td_clua::lua_pushlstring(lua, bytes.as_ptr() as *const libc::c_char, bytes.len())
+                }
+                LuaValue::Table(pairs) => {
+                    // a key and a value are pushed above the table at every level
+                    if td_clua::lua_checkstack(lua, 3) == 0 {
+                        drop(pairs);
+                        functions::raise_error(lua, "stack overflow pushing a nested table".to_string());
+                    }
+                    td_clua::lua_createtable(lua, 0, pairs.len() as libc::c_int);
+                    for (key, value) in pairs {
+                        // nil and NaN keys can't be stored in a table
+                        match key {
+                            LuaValue::Nil => continue,
+                            LuaValue::Number(n) if n.is_nan() => continue,
+                            _ => {}
+                        }
+                        key.push_to_lua(lua);
+                        value.push_to_lua(lua);
+                        td_clua::lua_rawset(lua, -3);
+                    }
+                }
+                LuaValue::Function(f) => { f.push_to_lua(lua); }
+                LuaValue::Userdata(r) | LuaValue::Thread(r) => r.push(lua),
+                LuaValue::LightUserdata(p) => td_clua::lua_pushlightuserdata(lua, p),
+            }
+        }
+        1
+    }
+}
+
+impl LuaRead for LuaValue {
+    fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<LuaValue> {
+        let index = unsafe { td_clua::lua_absindex(lua, index) };
+        read_value(lua, index, &mut HashSet::new(), 0)
+    }
+}
+
+/// Most levels of nested tables read into a `LuaValue`, deeper tables fail to read.
+pub const MAX_DEPTH: usize = 200;
+
+/// Reads the value at the absolute index `index`, tables already being read are kept in
+/// `visited` so that a cyclic table fails to read instead of recursing forever.
+fn read_value(lua: *mut lua_State, index: i32, visited: &mut HashSet<*const libc::c_void>, depth: usize)
+              -> Option<LuaValue>
+{
+    unsafe {
+        match td_clua::lua_type(lua, index) {
+            td_clua::LUA_TNONE | td_clua::LUA_TNIL => Some(LuaValue::Nil),
+            td_clua::LUA_TBOOLEAN => Some(LuaValue::Boolean(td_clua::lua_toboolean(lua, index) != 0)),
+            td_clua::LUA_TNUMBER => {
+                if td_clua::lua_isinteger(lua, index) != 0 {
+                    Some(LuaValue::Integer(td_clua::lua_tointegerx(lua, index, ::std::ptr::null_mut()) as i64))
+                } else {
+                    Some(LuaValue::Number(td_clua::lua_tonumberx(lua, index, ::std::ptr::null_mut())))
+                }
+            }
+            td_clua::LUA_TSTRING => {
+                let mut size = 0;
+                let data = td_clua::lua_tolstring(lua, index, &mut size);
+                Some(LuaValue::String(std::slice::from_raw_parts(data as *const u8, size).to_vec()))
+            }
+            td_clua::LUA_TTABLE => {
+                // the key and the value of every level are kept on the stack
+                if depth >= MAX_DEPTH || td_clua::lua_checkstack(lua, 3) == 0 {
+                    return None;
+                }
+                let ptr = td_clua::lua_topointer(lua, index);
+                if !visited.insert(ptr) {
+                    return None;
+                }
+                let mut pairs = Vec::new();
+                td_clua::lua_pushnil(lua);
+                while td_clua::lua_next(lua, index) != 0 {
+                    let top = td_clua::lua_gettop(lua);
+                    let pair = match (read_value(lua, top - 1, visited, depth + 1),
+                                                  read_value(lua, top, visited, depth + 1)) {
+                        (Some(key), Some(value)) => (key, value),
+                        _ => {
+                            td_clua::lua_pop(lua, 2);
+                            return None;
+                        }
+                    };
+                    pairs.push(pair);
+                    td_clua::lua_pop(lua, 1);
+                }
+                visited.remove(&ptr);
+                Some(LuaValue::Table(pairs))
+            }
+            td_clua::LUA_TFUNCTION => LuaFunctionRef::lua_read_at_position(lua, index).map(LuaValue::Function),
+            td_clua::LUA_TUSERDATA => Some(LuaValue::Userdata(LuaRef::new(lua, index))),
+            td_clua::LUA_TLIGHTUSERDATA => Some(LuaValue::LightUserdata(td_clua::lua_touserdata(lua, index))),
+            td_clua::LUA_TTHREAD => Some(LuaValue::Thread(LuaRef::new(lua, index))),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,78 @@
+use std::alloc::{GlobalAlloc, Layout};
+use std::ptr;
+
+use libc;
+
+/// Alignment of every block given to Lua, enough for any C type like `malloc`.
+const ALIGN: usize = 16;
+
+/// Allocator of a state made by `Lua::with_allocator`, counting the memory it hands out.
+///
+/// It is kept alive by the `Lua` owning the state until the state is closed.
+pub struct MemoryState {
+    allocator: Box<dyn GlobalAlloc>,
+    pub used: usize,
+    pub peak: usize,
+    pub limit: Option<usize>,
+}
+
+impl MemoryState {
+    pub fn new(allocator: Box<dyn GlobalAlloc>) -> MemoryState {
+        MemoryState {
+            allocator,
+            used: 0,
+            peak: 0,
+            limit: None,
+        }
+    }
+}
+
+/// The `lua_Alloc` function of the states made by `Lua::with_allocator`, `ud` being their
+/// `MemoryState`.
+pub extern "C" fn allocate(ud: *mut libc::c_void,
+                           ptr: *mut libc::c_void,
+                           osize: libc::size_t,
+                           nsize: libc::size_t)
+                           -> *mut libc::c_void {
+    let memory = unsafe { &mut *(ud as *mut MemoryState) };
+    // when `ptr` is null `osize` tells the kind of object being created
+    let osize = if ptr.is_null() { 0 } else { osize };
+
+    if nsize == 0 {
+        if !ptr.is_null() {
+            unsafe {
+                let layout = Layout::from_size_align_unchecked(osize, ALIGN);
+                memory.allocator.dealloc(ptr as *mut u8, layout);
+            }
+            memory.used -= osize;
+        }
+        return ptr::null_mut();
+    }
+
+    // Lua expects shrinking to always succeed
+    if nsize > osize {
+        if let Some(limit) = memory.limit {
+            if memory.used - osize + nsize > limit {
+                return ptr::null_mut();
+            }
+        }
+    }
+
+    let block = unsafe {
+        if ptr.is_null() {
+            match Layout::from_size_align(nsize, ALIGN) {
+                Ok(layout) => memory.allocator.alloc(layout),
+                Err(_) => ptr::null_mut(),
+            }
+        } else {
+            let layout = Layout::from_size_align_unchecked(osize, ALIGN);
+            memory.allocator.realloc(ptr as *mut u8, layout, nsize)
+        }
+    };
+    if block.is_null() {
+        return ptr::null_mut();
+    }
+    memory.used = memory.used - osize + nsize;
+    memory.peak = memory.peak.max(memory.used);
+    block as *mut libc::c_void
+}
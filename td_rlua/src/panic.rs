@@ -0,0 +1,57 @@
+use std::any::Any;
+use std::panic::{self, AssertUnwindSafe};
+
+use td_clua::lua_State;
+
+use functions::raise_error;
+use state;
+use LuaError;
+
+/// Payload of a panic caught by `protect`, with the message of the Lua error raised for it.
+pub type CaughtPanic = (String, Box<dyn Any + Send>);
+
+/// Runs Rust code called by Lua, turning a panic into a Lua error with the panic message
+/// since unwinding through the C frames of Lua is undefined behaviour.
+///
+/// The payload is kept for `resume_panic` when enabled by `Lua::set_resume_panics`.
+pub(crate) fn protect<F, R>(lua: *mut lua_State, f: F) -> R
+    where F: FnOnce() -> R
+{
+    let payload = match panic::catch_unwind(AssertUnwindSafe(f)) {
+        Ok(result) => return result,
+        Err(payload) => payload,
+    };
+    let message = format!("rust panic: {}", panic_message(&*payload));
+    let data = state::state_data(lua);
+    if data.resume_panics {
+        data.panic = Some((message.clone(), payload));
+    } else {
+        drop(payload);
+    }
+    raise_error(lua, message)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+/// Resumes the panic caught by `protect` once the protected call failed with its error.
+///
+/// A panic whose error was caught by the script itself is forgotten at the next error.
+pub(crate) fn resume_panic(lua: *mut lua_State, error: &LuaError) {
+    let (message, payload) = match state::state_data(lua).panic.take() {
+        Some(panic) => panic,
+        None => return,
+    };
+    if let LuaError::Runtime { message: ref error_message, .. } = *error {
+        if *error_message == message {
+            panic::resume_unwind(payload);
+        }
+    }
+}
@@ -93,4 +93,8 @@ impl<T> LuaRead for Vec<T> where T : LuaRead {
         }
         Some(result)
     }
+
+    fn lua_type_name() -> String {
+        "table".to_string()
+    }
 }
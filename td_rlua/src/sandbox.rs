@@ -0,0 +1,358 @@
+//! Restricted standard libraries and environments for untrusted scripts.
+
+use std::ffi::CString;
+use std::ops::{BitOr, BitOrAssign};
+
+use libc;
+use td_clua::{self, lua_State};
+
+use limits;
+use Lua;
+use LuaRead;
+use LuaRef;
+use LuaTableRef;
+
+/// Set of standard libraries opened by `Lua::new_with_libs`, combined with `|`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct StdLib(u32);
+
+impl StdLib {
+    /// Basic functions such as `print`, `pairs` or `load`, available as globals.
+    pub const BASE: StdLib = StdLib(1);
+    pub const COROUTINE: StdLib = StdLib(1 << 1);
+    pub const TABLE: StdLib = StdLib(1 << 2);
+    pub const IO: StdLib = StdLib(1 << 3);
+    pub const OS: StdLib = StdLib(1 << 4);
+    pub const STRING: StdLib = StdLib(1 << 5);
+    pub const UTF8: StdLib = StdLib(1 << 6);
+    pub const BIT32: StdLib = StdLib(1 << 7);
+    pub const MATH: StdLib = StdLib(1 << 8);
+    pub const DEBUG: StdLib = StdLib(1 << 9);
+    pub const PACKAGE: StdLib = StdLib(1 << 10);
+    /// The libraries without access to files, processes or the internals of the state.
+    pub const SAFE: StdLib = StdLib(1 | 1 << 1 | 1 << 2 | 1 << 5 | 1 << 6 | 1 << 8);
+    /// Every library, as opened by `Lua::openlibs`.
+    pub const ALL: StdLib = StdLib((1 << 11) - 1);
+    pub const NONE: StdLib = StdLib(0);
+
+    pub fn contains(self, libs: StdLib) -> bool {
+        self.0 & libs.0 == libs.0
+    }
+}
+
+impl BitOr for StdLib {
+    type Output = StdLib;
+
+    fn bitor(self, other: StdLib) -> StdLib {
+        StdLib(self.0 | other.0)
+    }
+}
+
+impl BitOrAssign for StdLib {
+    fn bitor_assign(&mut self, other: StdLib) {
+        self.0 |= other.0;
+    }
+}
+
+macro_rules! open_lib {
+    ($name:ident, $open:ident) => (
+        extern "C" fn $name(lua: *mut lua_State) -> libc::c_int {
+            unsafe { td_clua::$open(lua) }
+        }
+    )
+}
+
+open_lib!(open_base, luaopen_base);
+open_lib!(open_coroutine, luaopen_coroutine);
+open_lib!(open_table, luaopen_table);
+open_lib!(open_io, luaopen_io);
+open_lib!(open_os, luaopen_os);
+open_lib!(open_string, luaopen_string);
+open_lib!(open_utf8, luaopen_utf8);
+open_lib!(open_bit32, luaopen_bit32);
+open_lib!(open_math, luaopen_math);
+open_lib!(open_debug, luaopen_debug);
+open_lib!(open_package, luaopen_package);
+
+static LIBS: &[(StdLib, &str, td_clua::lua_CFunction)] = &[
+    (StdLib::BASE, "_G", open_base),
+    (StdLib::PACKAGE, "package", open_package),
+    (StdLib::COROUTINE, "coroutine", open_coroutine),
+    (StdLib::TABLE, "table", open_table),
+    (StdLib::IO, "io", open_io),
+    (StdLib::OS, "os", open_os),
+    (StdLib::STRING, "string", open_string),
+    (StdLib::MATH, "math", open_math),
+    (StdLib::UTF8, "utf8", open_utf8),
+    (StdLib::DEBUG, "debug", open_debug),
+    (StdLib::BIT32, "bit32", open_bit32),
+];
+
+/// Opens the given standard libraries, setting them as globals.
+pub(crate) fn open_libs(lua: *mut lua_State, libs: StdLib) {
+    for &(lib, name, open) in LIBS {
+        if libs.contains(lib) {
+            let name = CString::new(name).unwrap();
+            unsafe {
+                td_clua::luaL_requiref(lua, name.as_ptr(), open, 1);
+                td_clua::lua_pop(lua, 1);
+            }
+        }
+    }
+    if libs.contains(StdLib::COROUTINE) {
+        limits::limit_coroutines(lua);
+    }
+}
+
+/// Globals never copied into a sandbox since they read files or load native code.
+static BLOCKED: &[&str] = &["dofile", "loadfile", "require", "package"];
+
+static DEFAULT_GLOBALS: &[&str] = &[
+    "_VERSION", "assert", "error", "getmetatable", "ipairs", "load", "next", "pairs", "pcall",
+    "print", "rawequal", "rawget", "rawlen", "rawset", "select", "setmetatable", "tonumber",
+    "tostring", "type", "xpcall", "coroutine", "math", "string", "table", "utf8",
+    "os.clock", "os.date", "os.difftime", "os.time",
+];
+
+// `load` of a sandbox only accepts text chunks, which run in the sandbox unless given another
+// environment
+static SANDBOXED_LOAD: &str = r"
+    local load, env = ...
+    return function(chunk, name, mode, e)
+        return load(chunk, name, 't', e or env)
+    end
+";
+
+// `getmetatable` of a sandbox hides the metatable of the strings, whose `__index` is the
+// `string` library of the state
+static SANDBOXED_GETMETATABLE: &str = r"
+    local getmetatable = ...
+    return function(value)
+        if type(value) == 'string' then
+            return nil
+        end
+        return getmetatable(value)
+    end
+";
+
+/// Builds environment tables, used as `_ENV` by `Lua::exec_string_in`, which only contain
+/// whitelisted globals.
+///
+/// A whole library such as `string` is copied, so scripts can't modify the one of the state,
+/// while a single function is allowed with its path such as `os.time`. Allowed values missing
+/// from the state, for instance because their library isn't opened, are skipped.
+/// `dofile`, `loadfile`, `require` and `package` are never copied, `load` is replaced by a
+/// version refusing binary chunks and loading in the sandbox, and `getmetatable` by one
+/// returning nil for strings, the metatable of the strings being shared by the whole state.
+/// While `exec_string_in` runs, the methods of the strings, as in `("x"):rep(3)`, are looked up
+/// in the `string` table of the environment, so the functions denied there can't be reached
+/// through them. Functions of the sandbox called later from outside `exec_string_in` use the
+/// `string` library of the state again.
+///
+/// ```ignore
+/// let env = Sandbox::new().allow("os.getenv").deny("print").build(&mut lua);
+/// lua.try_exec_string_in::<_, ()>(&env, "x = os.time()").unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct Sandbox {
+    allowed: Vec<String>,
+    denied: Vec<String>,
+}
+
+impl Sandbox {
+    /// A sandbox allowing the basic functions, the libraries of `StdLib::SAFE` and the time
+    /// functions of `os`.
+    pub fn new() -> Sandbox {
+        Sandbox {
+            allowed: DEFAULT_GLOBALS.iter().map(|name| name.to_string()).collect(),
+            denied: Vec::new(),
+        }
+    }
+
+    /// A sandbox without any global.
+    pub fn empty() -> Sandbox {
+        Sandbox {
+            allowed: Vec::new(),
+            denied: Vec::new(),
+        }
+    }
+
+    /// Copies a global, or a value of a library such as `os.time`, into the environment.
+    pub fn allow(&mut self, path: &str) -> &mut Sandbox {
+        self.denied.retain(|denied| denied != path);
+        self.allowed.push(path.to_string());
+        self
+    }
+
+    /// Removes a global, or a value of a library such as `string.rep`, from the environment.
+    pub fn deny(&mut self, path: &str) -> &mut Sandbox {
+        self.allowed.retain(|allowed| allowed != path);
+        self.denied.push(path.to_string());
+        self
+    }
+
+    /// Creates a new environment table, each script should get its own.
+    pub fn build(&self, lua: &mut Lua) -> LuaTableRef {
+        let state = lua.state();
+        unsafe {
+            td_clua::lua_newtable(state);
+            let env = td_clua::lua_gettop(state);
+
+            for path in &self.allowed {
+                let global = path.split('.').next().unwrap_or("");
+                if BLOCKED.contains(&global) {
+                    continue;
+                }
+                push_global(state, path);
+                if td_clua::lua_isnil(state, -1) {
+                    td_clua::lua_pop(state, 1);
+                    continue;
+                }
+                if path == "load" {
+                    sandbox_load(state, env);
+                } else if path == "getmetatable" {
+                    sandbox_getmetatable(state);
+                } else if !path.contains('.') && td_clua::lua_istable(state, -1) {
+                    copy_table(state);
+                }
+                set_path(state, env, path, true);
+            }
+            for path in &self.denied {
+                td_clua::lua_pushnil(state);
+                set_path(state, env, path, false);
+            }
+
+            td_clua::lua_pushvalue(state, env);
+            let name = CString::new("_G").unwrap();
+            td_clua::lua_setfield(state, env, name.as_ptr());
+
+            let table = LuaTableRef::lua_read_at_position(state, env).unwrap();
+            td_clua::lua_pop(state, 1);
+            table
+        }
+    }
+}
+
+impl Default for Sandbox {
+    fn default() -> Sandbox {
+        Sandbox::new()
+    }
+}
+
+// pushes the value of a dotted path from the globals, or nil
+unsafe fn push_global(lua: *mut lua_State, path: &str) {
+    let mut names = path.split('.');
+    let first = CString::new(names.next().unwrap_or("")).unwrap();
+    td_clua::lua_getglobal(lua, first.as_ptr());
+    for name in names {
+        if !td_clua::lua_istable(lua, -1) {
+            td_clua::lua_pop(lua, 1);
+            td_clua::lua_pushnil(lua);
+            return;
+        }
+        let name = CString::new(name).unwrap();
+        td_clua::lua_getfield(lua, -1, name.as_ptr());
+        td_clua::lua_remove(lua, -2);
+    }
+}
+
+// replaces the table on the top of the stack by a shallow copy
+unsafe fn copy_table(lua: *mut lua_State) {
+    let source = td_clua::lua_gettop(lua);
+    td_clua::lua_newtable(lua);
+    td_clua::lua_pushnil(lua);
+    while td_clua::lua_next(lua, source) != 0 {
+        td_clua::lua_pushvalue(lua, -2);
+        td_clua::lua_insert(lua, -2);
+        td_clua::lua_rawset(lua, source + 1);
+    }
+    td_clua::lua_remove(lua, source);
+}
+
+/// Looks up the methods of the strings in the `string` table of an environment, until dropped.
+pub(crate) struct StringMethods {
+    lua: *mut lua_State,
+    index: Option<LuaRef>,
+}
+
+impl StringMethods {
+    pub(crate) fn restrict(lua: *mut lua_State, env: &LuaTableRef) -> StringMethods {
+        let name = CString::new("__index").unwrap();
+        unsafe {
+            td_clua::lua_pushstring(lua, name.as_ptr());
+            if td_clua::lua_getmetatable(lua, -1) == 0 {
+                td_clua::lua_pop(lua, 1);
+                return StringMethods { lua, index: None };
+            }
+            td_clua::lua_getfield(lua, -1, name.as_ptr());
+            let index = LuaRef::new(lua, -1);
+            td_clua::lua_pop(lua, 1);
+
+            let methods = env.query::<LuaTableRef, _>("string").unwrap_or_else(|| LuaTableRef::new(lua));
+            methods.as_lua_ref().push(lua);
+            td_clua::lua_setfield(lua, -2, name.as_ptr());
+            td_clua::lua_pop(lua, 2);
+            StringMethods { lua, index: Some(index) }
+        }
+    }
+}
+
+impl Drop for StringMethods {
+    fn drop(&mut self) {
+        let index = match self.index {
+            Some(ref index) => index,
+            None => return,
+        };
+        let name = CString::new("__index").unwrap();
+        unsafe {
+            td_clua::lua_pushstring(self.lua, name.as_ptr());
+            td_clua::lua_getmetatable(self.lua, -1);
+            index.push(self.lua);
+            td_clua::lua_setfield(self.lua, -2, name.as_ptr());
+            td_clua::lua_pop(self.lua, 2);
+        }
+    }
+}
+
+// replaces the `load` function on the top of the stack by the sandboxed version
+unsafe fn sandbox_load(lua: *mut lua_State, env: i32) {
+    let code = CString::new(SANDBOXED_LOAD).unwrap();
+    td_clua::luaL_loadstring(lua, code.as_ptr());
+    td_clua::lua_insert(lua, -2);
+    td_clua::lua_pushvalue(lua, env);
+    td_clua::lua_call(lua, 2, 1);
+}
+
+// replaces the `getmetatable` function on the top of the stack by the sandboxed version
+unsafe fn sandbox_getmetatable(lua: *mut lua_State) {
+    let code = CString::new(SANDBOXED_GETMETATABLE).unwrap();
+    td_clua::luaL_loadstring(lua, code.as_ptr());
+    td_clua::lua_insert(lua, -2);
+    td_clua::lua_call(lua, 1, 1);
+}
+
+// pops the value on the top of the stack and stores it at the dotted path of the table at
+// `env`, creating the missing tables or giving up
+unsafe fn set_path(lua: *mut lua_State, env: i32, path: &str, create: bool) {
+    let value = td_clua::lua_gettop(lua);
+    let names: Vec<CString> = path.split('.').map(|name| CString::new(name).unwrap()).collect();
+    let (last, parents) = names.split_last().unwrap();
+    td_clua::lua_pushvalue(lua, env);
+    for name in parents {
+        td_clua::lua_getfield(lua, -1, name.as_ptr());
+        if !td_clua::lua_istable(lua, -1) {
+            if !create {
+                td_clua::lua_settop(lua, value - 1);
+                return;
+            }
+            td_clua::lua_pop(lua, 1);
+            td_clua::lua_newtable(lua);
+            td_clua::lua_pushvalue(lua, -1);
+            td_clua::lua_setfield(lua, -3, name.as_ptr());
+        }
+        td_clua::lua_remove(lua, -2);
+    }
+    td_clua::lua_pushvalue(lua, value);
+    td_clua::lua_setfield(lua, -2, last.as_ptr());
+    td_clua::lua_settop(lua, value - 1);
+}
@@ -0,0 +1,76 @@
+use std::any::TypeId;
+use std::cell::Cell;
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::mem;
+use std::ptr;
+use std::rc::Rc;
+
+use libc;
+use td_clua::{self, lua_State};
+
+use error::StackFrame;
+use limits::Limits;
+use memory::MemoryState;
+use panic::{self, CaughtPanic};
+use userdata::MetatableRefs;
+
+/// Called with the error message and the stack traceback when a protected call fails.
+pub type ErrorHandler = Box<dyn FnMut(&str, &str)>;
+
+/// Rust side data attached to a lua state.
+///
+/// It is kept as a userdata in the registry, so it is shared by every `Lua` object and every
+/// thread using the same state, and scripts have no way to reach it.
+#[derive(Default)]
+pub struct StateData {
+    pub error_handler: Option<ErrorHandler>,
+    /// traceback captured by the last call of the error handler
+    pub traceback: Option<String>,
+    /// stack frames captured by the last call of the error handler
+    pub frames: Vec<StackFrame>,
+    pub capture_locals: bool,
+    pub limits: Limits,
+    /// allocator of a state made by `Lua::with_allocator`, freed when the state is closed
+    pub memory: Option<*mut MemoryState>,
+    pub resume_panics: bool,
+    /// panic caught in a Rust function, kept until its error reaches the Rust side
+    pub panic: Option<CaughtPanic>,
+    /// registry references of the metatables of the user data, by type
+    pub metatables: HashMap<TypeId, MetatableRefs>,
+    /// set once the state is closed, shared with the references to its values
+    pub closed: Rc<Cell<bool>>,
+}
+
+static STATE_KEY: &[u8] = b"__td_rlua_state\0";
+
+extern "C" fn destructor(lua: *mut lua_State) -> libc::c_int {
+    panic::protect(lua, || unsafe {
+        let obj = td_clua::lua_touserdata(lua, 1) as *mut StateData;
+        (*obj).closed.set(true);
+        ptr::drop_in_place(obj);
+    });
+    0
+}
+
+/// Returns the data attached to the state, creating it the first time.
+pub fn state_data<'a>(lua: *mut lua_State) -> &'a mut StateData {
+    let key = STATE_KEY.as_ptr() as *const libc::c_char;
+    unsafe {
+        td_clua::lua_getfield(lua, td_clua::LUA_REGISTRYINDEX, key);
+        let mut data = td_clua::lua_touserdata(lua, -1) as *mut StateData;
+        td_clua::lua_pop(lua, 1);
+        if data.is_null() {
+            data = td_clua::lua_newuserdata(lua, mem::size_of::<StateData>() as libc::size_t)
+                as *mut StateData;
+            ptr::write(data, StateData::default());
+            td_clua::lua_newtable(lua);
+            let gc = CString::new("__gc").unwrap();
+            td_clua::lua_pushcfunction(lua, destructor);
+            td_clua::lua_setfield(lua, -2, gc.as_ptr());
+            td_clua::lua_setmetatable(lua, -2);
+            td_clua::lua_setfield(lua, td_clua::LUA_REGISTRYINDEX, key);
+        }
+        &mut *data
+    }
+}
@@ -15,6 +15,10 @@ macro_rules! tuple_impl {
             fn lua_read_with_pop_impl(lua: *mut td_clua::lua_State, index: i32, _pop: i32) -> Option<($ty,)> {
                 LuaRead::lua_read_at_position(lua, index).map(|v| (v,))
             }
+
+            fn lua_read_checked(lua: *mut td_clua::lua_State, index: i32) -> Result<($ty,), (i32, String)> {
+                $ty::lua_read_checked(lua, index).map(|v| (v,))
+            }
         }
     );
 
@@ -64,6 +68,19 @@ macro_rules! tuple_impl {
                 Some(($first, $($other),+))
 
             }
+
+            fn lua_read_checked(lua: *mut td_clua::lua_State, index: i32) -> Result<($first, $($other),+), (i32, String)> {
+                let mut i = index;
+                let $first = $first::lua_read_checked(lua, i)?;
+                i += 1;
+
+                $(
+                    let $other = $other::lua_read_checked(lua, i).map_err(|(offset, expected)| (offset + i - index, expected))?;
+                    i += 1;
+                )+
+
+                Ok(($first, $($other),+))
+            }
         }
 
         tuple_impl!($($other),+);
@@ -8,7 +8,10 @@ use std::ptr;
 use libc;
 use td_clua;
 use td_clua::lua_State;
-use Lua;
+use panic;
+use state;
+use error;
+use functions::{self, FunctionArity, FunctionExt};
 use LuaPush;
 use LuaRead;
 use LuaTable;
@@ -16,29 +19,165 @@ use LuaTable;
 // Called when an object inside Lua is being dropped.
 #[inline]
 extern "C" fn destructor_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int {
-    unsafe {
-        let obj = td_clua::lua_touserdata(lua, -1);
-        ptr::drop_in_place(obj as *mut T);
-        0
-    }
+    panic::protect(lua, || unsafe {
+        // the light user data made by `LuaStruct::new_light` share the metatable but are
+        // never collected
+        if td_clua::lua_type(lua, -1) == td_clua::LUA_TUSERDATA {
+            let obj = td_clua::lua_touserdata(lua, -1);
+            ptr::drop_in_place(obj as *mut T);
+        }
+    });
+    0
 }
 
 extern "C" fn constructor_wrapper<T>(lua: *mut td_clua::lua_State) -> libc::c_int
 where
     T: NewStruct + Any,
 {
-    let t = T::new();
-    let lua_data_raw =
-        unsafe { td_clua::lua_newuserdata(lua, mem::size_of::<T>() as libc::size_t) };
-    unsafe {
-        ptr::write(lua_data_raw as *mut _, t);
+    let t = panic::protect(lua, T::new);
+    unsafe { push_struct(t, lua) }
+}
+
+/// Pushes an object as a user data with the metatable of its `LuaStruct`, so it has the
+/// methods and metamethods defined there, for instance to return a new object from a method.
+///
+/// # Safety
+///
+/// `lua` must be a valid state.
+pub unsafe fn push_struct<T>(data: T, lua: *mut td_clua::lua_State) -> i32
+where
+    T: NewStruct + Any,
+{
+    let lua_data_raw = td_clua::lua_newuserdata(lua, mem::size_of::<T>() as libc::size_t);
+    ptr::write(lua_data_raw as *mut _, data);
+    push_metatable::<T>(lua);
+    td_clua::lua_setmetatable(lua, -2);
+    1
+}
+
+/// Picks `push_struct` for the types implementing `NewStruct`, `push_userdata` otherwise, in
+/// the `LuaPush` implemented by `#[derive(LuaPush)]` with `#[lua(userdata)]`:
+///
+/// ```ignore
+/// use td_rlua::userdata::{PushStruct, PushUserdata};
+/// unsafe { (&td_rlua::userdata::PushProbe::<Self>::new()).push_value(self, lua) }
+/// ```
+///
+/// `PushStruct` is implemented for the probe and `PushUserdata` for a reference to it, so the
+/// method call only falls back to `PushUserdata` when `PushStruct` doesn't apply.
+#[doc(hidden)]
+pub struct PushProbe<T>(PhantomData<T>);
+
+impl<T> PushProbe<T> {
+    pub fn new() -> PushProbe<T> {
+        PushProbe(PhantomData)
+    }
+}
+
+impl<T> Default for PushProbe<T> {
+    fn default() -> PushProbe<T> {
+        PushProbe::new()
     }
-    let typeid = CString::new(T::name()).unwrap();
+}
+
+#[doc(hidden)]
+pub trait PushStruct<T> {
+    /// # Safety
+    ///
+    /// `lua` must be a valid state.
+    unsafe fn push_value(&self, data: T, lua: *mut td_clua::lua_State) -> i32;
+}
+
+impl<T> PushStruct<T> for PushProbe<T>
+where
+    T: NewStruct + Any,
+{
+    unsafe fn push_value(&self, data: T, lua: *mut td_clua::lua_State) -> i32 {
+        push_struct(data, lua)
+    }
+}
+
+#[doc(hidden)]
+pub trait PushUserdata<T> {
+    /// # Safety
+    ///
+    /// `lua` must be a valid state.
+    unsafe fn push_value(&self, data: T, lua: *mut td_clua::lua_State) -> i32;
+}
+
+impl<T> PushUserdata<T> for &PushProbe<T>
+where
+    T: Any,
+{
+    unsafe fn push_value(&self, data: T, lua: *mut td_clua::lua_State) -> i32 {
+        push_userdata(data, lua, |_| {})
+    }
+}
+
+/// Kinds of user data of a same type that don't share their metatable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum MetatableKind {
+    Struct,
+    Userdata,
+    Light,
+}
+
+/// Registry references of the metatables of a type, by `MetatableKind`.
+pub(crate) type MetatableRefs = [Option<libc::c_int>; 3];
+
+// registry references of the metatables of the user data of type `T`
+fn metatable_refs<T>(lua: *mut td_clua::lua_State) -> MetatableRefs
+where
+    T: Any,
+{
+    state::state_data(lua).metatables.get(&TypeId::of::<T>()).cloned().unwrap_or_default()
+}
+
+// pushes the metatable of the user data of type `T` and of the given kind, returning false
+// when it is a new empty table
+unsafe fn push_cached_metatable<T>(lua: *mut td_clua::lua_State, kind: MetatableKind) -> bool
+where
+    T: Any,
+{
+    if let Some(reference) = metatable_refs::<T>(lua)[kind as usize] {
+        td_clua::lua_rawgeti(lua, td_clua::LUA_REGISTRYINDEX, reference as td_clua::lua_Integer);
+        return true;
+    }
+    td_clua::lua_newtable(lua);
+    td_clua::lua_pushvalue(lua, -1);
+    let reference = td_clua::luaL_ref(lua, td_clua::LUA_REGISTRYINDEX);
+    state::state_data(lua).metatables.entry(TypeId::of::<T>()).or_default()[kind as usize] = Some(reference);
+    false
+}
+
+// pushes the metatable of the objects of type `T`, creating it the first time
+fn push_metatable<T>(lua: *mut td_clua::lua_State)
+where
+    T: NewStruct + Any,
+{
     unsafe {
-        td_clua::lua_getglobal(lua, typeid.as_ptr());
-        td_clua::lua_setmetatable(lua, -2);
+        if push_cached_metatable::<T>(lua, MetatableKind::Struct) {
+            return;
+        }
+
+        // index "__name" is used by Lua to describe the objects
+        "__name".push_to_lua(lua);
+        T::name().push_to_lua(lua);
+        td_clua::lua_settable(lua, -3);
+
+        // index "__gc" call the object's destructor
+        "__gc".push_to_lua(lua);
+        td_clua::lua_pushcfunction(lua, destructor_wrapper::<T>);
+        td_clua::lua_settable(lua, -3);
+
+        // the methods are found through `__index` until properties are defined
+        td_clua::lua_newtable(lua);
+        td_clua::lua_pushvalue(lua, -1);
+        td_clua::lua_setfield(lua, -3, METHODS_KEY.as_ptr() as *const libc::c_char);
+        "__index".push_to_lua(lua);
+        td_clua::lua_insert(lua, -2);
+        td_clua::lua_rawset(lua, -3);
     }
-    1
 }
 
 // constructor direct create light object,
@@ -48,60 +187,144 @@ extern "C" fn constructor_light_wrapper<T>(lua: *mut td_clua::lua_State) -> libc
 where
     T: NewStruct + Any,
 {
-    let t = Box::into_raw(Box::new(T::new()));
-    push_lightuserdata(unsafe { &mut *t }, lua, |_| {});
-    let typeid = CString::new(T::name()).unwrap();
+    let t = Box::into_raw(Box::new(panic::protect(lua, T::new)));
+    unsafe {
+        td_clua::lua_pushlightuserdata(lua, t as *mut libc::c_void);
+    }
+    push_metatable::<T>(lua);
     unsafe {
-        td_clua::lua_getglobal(lua, typeid.as_ptr());
         td_clua::lua_setmetatable(lua, -2);
     }
     1
 }
 
+// a constructor defined with `LuaStruct::constructor`
+struct Constructor<F>(F);
+
+impl<F, P> FunctionExt<P> for Constructor<F>
+where
+    F: FunctionExt<P>,
+{
+    type Output = Constructed<F::Output>;
+
+    fn call_mut(&mut self, params: P) -> Constructed<F::Output> {
+        Constructed(self.0.call_mut(params))
+    }
+}
+
+// an object returned by a `Constructor`, pushed with the metatable of its `LuaStruct`
+struct Constructed<T>(T);
+
+impl<T> LuaPush for Constructed<T>
+where
+    T: NewStruct + Any,
+{
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        unsafe { push_struct(self.0, lua) }
+    }
+}
+
+static METHODS_KEY: &[u8] = b"__methods\0";
+static GETTERS_KEY: &[u8] = b"__getters\0";
+static SETTERS_KEY: &[u8] = b"__setters\0";
+
+static CONSTRUCTORS_KEY: &[u8] = b"__constructors\0";
+
+// `__call` of the constructor table exported by `LuaStruct::create`, called with the table and
+// the arguments, the constructors by number of arguments being its upvalue
+extern "C" fn constructor_dispatch<T>(lua: *mut td_clua::lua_State) -> libc::c_int
+where
+    T: NewStruct,
+{
+    unsafe {
+        let nargs = td_clua::lua_gettop(lua) - 1;
+        td_clua::lua_rawgeti(lua, td_clua::lua_upvalueindex(1), nargs as td_clua::lua_Integer);
+        if td_clua::lua_isnil(lua, -1) {
+            functions::raise_error(lua, format!("no constructor of {} takes {} arguments", T::name(), nargs));
+        }
+        td_clua::lua_insert(lua, 1);
+        td_clua::lua_remove(lua, 2);
+        td_clua::lua_call(lua, nargs, 1);
+    }
+    1
+}
+
+// `__index` of the structs with properties, the getters and the methods being its upvalues
+extern "C" fn index_dispatch(lua: *mut td_clua::lua_State) -> libc::c_int {
+    unsafe {
+        td_clua::lua_pushvalue(lua, 2);
+        td_clua::lua_rawget(lua, td_clua::lua_upvalueindex(1));
+        if td_clua::lua_isnil(lua, -1) {
+            td_clua::lua_pop(lua, 1);
+            td_clua::lua_pushvalue(lua, 2);
+            td_clua::lua_rawget(lua, td_clua::lua_upvalueindex(2));
+            return 1;
+        }
+        td_clua::lua_pushvalue(lua, 1);
+        td_clua::lua_call(lua, 1, 1);
+    }
+    1
+}
+
+// `__newindex` of the structs with properties, the setters being its upvalue
+extern "C" fn newindex_dispatch<T>(lua: *mut td_clua::lua_State) -> libc::c_int
+where
+    T: NewStruct,
+{
+    unsafe {
+        td_clua::lua_pushvalue(lua, 2);
+        td_clua::lua_rawget(lua, td_clua::lua_upvalueindex(1));
+        if td_clua::lua_isnil(lua, -1) {
+            // the key is dropped with its scope, before the error is raised
+            let message = {
+                let key = match td_clua::lua_type(lua, 2) {
+                    td_clua::LUA_TSTRING => error::error_message(lua, 2),
+                    _ => format!("({})", error::type_name(lua, 2)),
+                };
+                format!("cannot set unknown field '{}' of {}", key, T::name())
+            };
+            functions::raise_error(lua, message);
+        }
+        td_clua::lua_pushvalue(lua, 1);
+        td_clua::lua_pushvalue(lua, 3);
+        td_clua::lua_call(lua, 2, 0);
+    }
+    0
+}
+
 /// Pushes an object as a user data.
 ///
 /// In Lua, a user data is anything that is not recognized by Lua. When the script attempts to
 /// copy a user data, instead only a reference to the data is copied.
 ///
 /// The way a Lua script can use the user data depends on the content of the **metatable**, which
-/// is a Lua table linked to the object.
+/// is a Lua table linked to the object. All the objects of type `T` share the same metatable.
 ///
 /// # Arguments
 ///
-///  - `metatable`: Function that fills the metatable of the object.
+///  - `metatable`: Function that fills the metatable of the object, only called the first time
+///    an object of type `T` is pushed.
 ///
 pub fn push_userdata<'a, T, F>(data: T, lua: *mut td_clua::lua_State, mut metatable: F) -> i32
 where
     F: FnMut(LuaTable),
     T: 'a + Any,
 {
-    let typeid = format!("{:?}", TypeId::of::<T>());
     let lua_data_raw =
         unsafe { td_clua::lua_newuserdata(lua, mem::size_of::<T>() as libc::size_t) };
 
-    // creating a metatable
     unsafe {
         ptr::write(lua_data_raw as *mut _, data);
 
-        td_clua::lua_newtable(lua);
-
-        // index "__typeid" corresponds to the hash of the TypeId of T
-        "__typeid".push_to_lua(lua);
-        typeid.push_to_lua(lua);
-        td_clua::lua_settable(lua, -3);
-
-        // index "__gc" call the object's destructor
-        {
+        if !push_cached_metatable::<T>(lua, MetatableKind::Userdata) {
+            // index "__gc" call the object's destructor
             "__gc".push_to_lua(lua);
-
             td_clua::lua_pushcfunction(lua, destructor_wrapper::<T>);
-
             td_clua::lua_settable(lua, -3);
-        }
 
-        // calling the metatable closure
-        {
-            metatable(LuaRead::lua_read(lua).unwrap());
+            // calling the metatable closure
+            td_clua::lua_pushvalue(lua, -1);
+            metatable(LuaRead::lua_read_with_pop(lua, -1, 1).unwrap());
         }
 
         td_clua::lua_setmetatable(lua, -2);
@@ -116,11 +339,13 @@ where
 /// copy a user data, instead only a reference to the data is copied.
 ///
 /// The way a Lua script can use the user data depends on the content of the **metatable**, which
-/// is a Lua table linked to the object.
+/// is a Lua table linked to the object. All the objects of type `T` share the same metatable,
+/// but beware that Lua gives a single metatable to all the light user data.
 ///
 /// # Arguments
 ///
-///  - `metatable`: Function that fills the metatable of the object.
+///  - `metatable`: Function that fills the metatable of the object, only called the first time
+///    an object of type `T` is pushed.
 ///
 pub fn push_lightuserdata<'a, T, F>(
     data: &'a mut T,
@@ -131,23 +356,13 @@ where
     F: FnMut(LuaTable),
     T: 'a + Any,
 {
-    let typeid = format!("{:?}", TypeId::of::<T>());
     unsafe {
         td_clua::lua_pushlightuserdata(lua, mem::transmute(data));
-    };
-
-    // creating a metatable
-    unsafe {
-        td_clua::lua_newtable(lua);
-
-        // index "__typeid" corresponds to the hash of the TypeId of T
-        "__typeid".push_to_lua(lua);
-        typeid.push_to_lua(lua);
-        td_clua::lua_settable(lua, -3);
 
-        // calling the metatable closure
-        {
-            metatable(LuaRead::lua_read(lua).unwrap());
+        if !push_cached_metatable::<T>(lua, MetatableKind::Light) {
+            // calling the metatable closure
+            td_clua::lua_pushvalue(lua, -1);
+            metatable(LuaRead::lua_read_with_pop(lua, -1, 1).unwrap());
         }
 
         td_clua::lua_setmetatable(lua, -2);
@@ -156,32 +371,148 @@ where
     1
 }
 
+/// Reads an object pushed by `push_userdata` or `push_struct`, the type being checked by
+/// comparing the metatable of the value with the ones of `T`.
 ///
+/// Lua gives a single metatable to all the light user data, so an object pushed by
+/// `push_lightuserdata` can't be told apart and isn't read, only the light user data made by
+/// the constructor of a `LuaStruct::new_light` are.
 pub fn read_userdata<'t, 'c, T>(lua: *mut td_clua::lua_State, index: i32) -> Option<&'t mut T>
+where
+    T: 'static + Any,
+{
+    userdata_ptr::<T>(lua, index).map(|data| unsafe { &mut *data })
+}
+
+/// Reads an object like `read_userdata`, as a shared reference.
+///
+/// Both operands of a metamethod may be the same object, as in `v + v`, so they must be read
+/// this way rather than as `&mut T`.
+///
+/// # Safety
+///
+/// `lua` must be a valid state, and the object must not be borrowed mutably while the
+/// reference is alive.
+pub unsafe fn read_userdata_ref<'t, T>(lua: *mut td_clua::lua_State, index: i32) -> Option<&'t T>
+where
+    T: 'static + Any,
+{
+    userdata_ptr::<T>(lua, index).map(|data| &*data)
+}
+
+fn userdata_ptr<T>(lua: *mut td_clua::lua_State, index: i32) -> Option<*mut T>
 where
     T: 'static + Any,
 {
     unsafe {
-        let expected_typeid = format!("{:?}", TypeId::of::<T>());
         let data_ptr = td_clua::lua_touserdata(lua, index);
         if data_ptr.is_null() {
             return None;
         }
+        let light = td_clua::lua_type(lua, index) == td_clua::LUA_TLIGHTUSERDATA;
+        let mut references = metatable_refs::<T>(lua);
+        references[MetatableKind::Light as usize] = None;
+        if light {
+            references[MetatableKind::Userdata as usize] = None;
+        }
         if td_clua::lua_getmetatable(lua, index) == 0 {
             return None;
         }
 
-        "__typeid".push_to_lua(lua);
-        td_clua::lua_gettable(lua, -2);
-        match <String as LuaRead>::lua_read(lua) {
-            Some(ref val) if val == &expected_typeid => {}
-            _ => {
-                td_clua::lua_pop(lua, 2);
-                return None;
-            }
+        let found = references.iter().filter_map(|&reference| reference).any(|reference| {
+            td_clua::lua_rawgeti(lua, td_clua::LUA_REGISTRYINDEX, reference as td_clua::lua_Integer);
+            let equal = td_clua::lua_rawequal(lua, -1, -2) != 0;
+            td_clua::lua_pop(lua, 1);
+            equal
+        });
+        td_clua::lua_pop(lua, 1);
+        if found {
+            Some(data_ptr as *mut T)
+        } else {
+            None
+        }
+    }
+}
+
+/// Metamethods that can be defined with `LuaStruct::meta`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MetaMethod {
+    /// `a + b`
+    Add,
+    /// `a - b`
+    Sub,
+    /// `a * b`
+    Mul,
+    /// `a / b`
+    Div,
+    /// `a % b`
+    Mod,
+    /// `a ^ b`
+    Pow,
+    /// `-a`, called with the object twice, so it must be read as `&T`.
+    Unm,
+    /// `a // b`
+    IDiv,
+    /// `a & b`
+    BAnd,
+    /// `a | b`
+    BOr,
+    /// `a ~ b`
+    BXor,
+    /// `~a`, called with the object twice, so it must be read as `&T`.
+    BNot,
+    /// `a << b`
+    Shl,
+    /// `a >> b`
+    Shr,
+    /// `a .. b`, the object may be either operand.
+    Concat,
+    /// `#a`
+    Len,
+    /// `a == b`, only called for two different user data.
+    Eq,
+    /// `a < b`
+    Lt,
+    /// `a <= b`
+    Le,
+    /// `a(...)`
+    Call,
+    /// `tostring(a)`, must return a string.
+    ToString,
+    /// Closing of a to-be-closed variable, only used from Lua 5.4.
+    Close,
+    /// `pairs(a)`, must return the iterator function, state and initial value.
+    Pairs,
+}
+
+impl MetaMethod {
+    /// Name of the metamethod in the metatable, like `"__add"`.
+    pub fn name(self) -> &'static str {
+        match self {
+            MetaMethod::Add => "__add",
+            MetaMethod::Sub => "__sub",
+            MetaMethod::Mul => "__mul",
+            MetaMethod::Div => "__div",
+            MetaMethod::Mod => "__mod",
+            MetaMethod::Pow => "__pow",
+            MetaMethod::Unm => "__unm",
+            MetaMethod::IDiv => "__idiv",
+            MetaMethod::BAnd => "__band",
+            MetaMethod::BOr => "__bor",
+            MetaMethod::BXor => "__bxor",
+            MetaMethod::BNot => "__bnot",
+            MetaMethod::Shl => "__shl",
+            MetaMethod::Shr => "__shr",
+            MetaMethod::Concat => "__concat",
+            MetaMethod::Len => "__len",
+            MetaMethod::Eq => "__eq",
+            MetaMethod::Lt => "__lt",
+            MetaMethod::Le => "__le",
+            MetaMethod::Call => "__call",
+            MetaMethod::ToString => "__tostring",
+            MetaMethod::Close => "__close",
+            MetaMethod::Pairs => "__pairs",
         }
-        td_clua::lua_pop(lua, 2);
-        Some(mem::transmute(data_ptr))
     }
 }
 
@@ -190,6 +521,13 @@ pub trait NewStruct {
     fn name() -> &'static str;
 }
 
+/// Methods registered all at once by `LuaStruct::def_methods`.
+///
+/// With the `derive` feature it is implemented by the `#[lua_methods]` attribute.
+pub trait LuaMethods: Sized {
+    fn lua_methods(lua_struct: &mut LuaStruct<Self>);
+}
+
 pub struct LuaStruct<T> {
     lua: *mut lua_State,
     light: bool,
@@ -216,83 +554,127 @@ where
         }
     }
 
-    pub fn ensure_matetable(&mut self) {
-        let name = T::name();
-        let mut lua = Lua::from_existing_state(self.lua, false);
-        match lua.query::<LuaTable, _>(name) {
-            Some(_) => {}
-            None => unsafe {
-                td_clua::lua_newtable(self.lua);
-
-                let typeid = format!("{:?}", TypeId::of::<T>());
-                // index "__name" corresponds to the hash of the TypeId of T
-                "__typeid".push_to_lua(self.lua);
-                typeid.push_to_lua(self.lua);
-                td_clua::lua_settable(self.lua, -3);
-
-                // index "__gc" call the object's destructor
-                if !self.light {
-                    "__gc".push_to_lua(self.lua);
-
-                    td_clua::lua_pushcfunction(self.lua, destructor_wrapper::<T>);
-
-                    td_clua::lua_settable(self.lua, -3);
-                }
-
-                "__index".push_to_lua(self.lua);
-                td_clua::lua_newtable(self.lua);
-                td_clua::lua_rawset(self.lua, -3);
+    /// Whether the metatable of `T` was created in the state, by a `LuaStruct` or when
+    /// pushing an object with `push_struct`.
+    pub fn is_registered(lua: *mut lua_State) -> bool {
+        metatable_refs::<T>(lua)[MetatableKind::Struct as usize].is_some()
+    }
 
-                let name = CString::new(name).unwrap();
-                td_clua::lua_setglobal(self.lua, name.as_ptr());
-            },
+    /// Creates the metatable of the objects, kept in the registry where scripts can't reach it.
+    pub fn ensure_matetable(&mut self) {
+        push_metatable::<T>(self.lua);
+        unsafe {
+            td_clua::lua_pop(self.lua, 1);
         }
     }
 
+    /// Exports the constructors as the global `T::name()`, `T::name()(...)` creating an object.
+    ///
+    /// The global is only a way to call the constructors, replacing it doesn't affect the
+    /// objects.
     pub fn create(&mut self) -> &mut LuaStruct<T> {
-        self.ensure_matetable();
+        self.push_constructors();
         unsafe {
-            let typeid = CString::new(T::name()).unwrap();
-            td_clua::lua_getglobal(self.lua, typeid.as_ptr());
-            if td_clua::lua_istable(self.lua, -1) {
-                td_clua::lua_newtable(self.lua);
-                "__call".push_to_lua(self.lua);
+            // the table and its metatable are put below the constructors
+            td_clua::lua_newtable(self.lua);
+            td_clua::lua_insert(self.lua, -2);
+            td_clua::lua_newtable(self.lua);
+            td_clua::lua_insert(self.lua, -2);
+            td_clua::lua_pushcclosure(self.lua, constructor_dispatch::<T>, 1);
+            td_clua::lua_setfield(self.lua, -2, b"__call\0".as_ptr() as *const libc::c_char);
+            td_clua::lua_setmetatable(self.lua, -2);
+            let name = CString::new(T::name()).unwrap();
+            td_clua::lua_setglobal(self.lua, name.as_ptr());
+        }
+        self
+    }
 
+    // pushes the constructors by number of arguments, creating them with `T::new` as the one
+    // without arguments the first time
+    fn push_constructors(&mut self) {
+        push_metatable::<T>(self.lua);
+        unsafe {
+            td_clua::lua_getfield(self.lua, -1, CONSTRUCTORS_KEY.as_ptr() as *const libc::c_char);
+            if !td_clua::lua_istable(self.lua, -1) {
+                td_clua::lua_pop(self.lua, 1);
+                td_clua::lua_newtable(self.lua);
                 if self.light {
                     td_clua::lua_pushcfunction(self.lua, constructor_light_wrapper::<T>);
-                    td_clua::lua_settable(self.lua, -3);
                 } else {
                     td_clua::lua_pushcfunction(self.lua, constructor_wrapper::<T>);
-                    td_clua::lua_settable(self.lua, -3);
                 }
-
-                td_clua::lua_setmetatable(self.lua, -2);
+                td_clua::lua_rawseti(self.lua, -2, 0);
+                td_clua::lua_pushvalue(self.lua, -1);
+                td_clua::lua_setfield(self.lua, -3, CONSTRUCTORS_KEY.as_ptr() as *const libc::c_char);
             }
+            td_clua::lua_remove(self.lua, -2);
+        }
+    }
+
+    pub fn def<P>(&mut self, name: &str, param: P) -> &mut LuaStruct<T>
+    where
+        P: LuaPush,
+    {
+        self.with_metatable_field("__methods", |methods| {
+            methods.set(name, param);
+        });
+        self
+    }
+
+    /// Defines a constructor taking arguments, `Point(1, 2)` calling the constructor taking
+    /// two arguments:
+    ///
+    /// ```ignore
+    /// lua_struct.constructor(td_rlua::function2(|x: f64, y: f64| Point { x, y }));
+    /// ```
+    ///
+    /// Constructors are told apart by their number of arguments, `T::new` stays the one
+    /// without arguments unless another one is given. The object returned is pushed with
+    /// `push_struct`, so it gets the metatable whatever the `LuaPush` implementation of `T`.
+    /// They are called through the global exported by `create`.
+    pub fn constructor<F, P>(&mut self, function: F) -> &mut LuaStruct<T>
+    where
+        F: FunctionExt<P, Output = T> + FunctionArity + 'static,
+        P: LuaRead + 'static,
+    {
+        self.push_constructors();
+        functions::push_closure(
+            self.lua,
+            Constructor(function),
+            functions::wrapper::<Constructor<F>, P, Constructed<T>>,
+        );
+        unsafe {
+            td_clua::lua_rawseti(self.lua, -2, F::ARITY as td_clua::lua_Integer);
             td_clua::lua_pop(self.lua, 1);
         }
         self
     }
 
-    pub fn def<P>(&mut self, name: &str, param: P) -> &mut LuaStruct<T>
+    /// Defines a metamethod, so the objects work with the Lua operators:
+    ///
+    /// ```ignore
+    /// lua_struct.meta(MetaMethod::Add, td_rlua::function2(|a: &Vec2, b: &Vec2| {
+    ///     Vec2 { x: a.x + b.x, y: a.y + b.y }
+    /// }));
+    /// ```
+    ///
+    /// The operands may be the same object, so they are read as `&T`, with `read_userdata_ref`.
+    /// New objects returned with `push_struct` get the same metamethods.
+    pub fn meta<P>(&mut self, method: MetaMethod, function: P) -> &mut LuaStruct<T>
     where
         P: LuaPush,
     {
-        let tname = T::name();
-        let mut lua = Lua::from_existing_state(self.lua, false);
-        match lua.query::<LuaTable, _>(tname) {
-            Some(mut table) => {
-                match table.query::<LuaTable, _>("__index") {
-                    Some(mut index) => {
-                        index.set(name, param);
-                    }
-                    None => {
-                        let mut index = table.empty_table("__index");
-                        index.set(name, param);
-                    }
-                };
-            }
-            None => (),
-        };
+        let mut metatable = self.metatable();
+        metatable.set(method.name(), function);
+        self
+    }
+
+    /// Defines every method of `T::lua_methods`.
+    pub fn def_methods(&mut self) -> &mut LuaStruct<T>
+    where
+        T: LuaMethods,
+    {
+        T::lua_methods(self);
         self
     }
 
@@ -301,22 +683,81 @@ where
         name: &str,
         func: extern "C" fn(*mut td_clua::lua_State) -> libc::c_int,
     ) -> &mut LuaStruct<T> {
-        let tname = T::name();
-        let mut lua = Lua::from_existing_state(self.lua, false);
-        match lua.query::<LuaTable, _>(tname) {
-            Some(mut table) => {
-                match table.query::<LuaTable, _>("__index") {
-                    Some(mut index) => {
-                        index.register(name, func);
-                    }
-                    None => {
-                        let mut index = table.empty_table("__index");
-                        index.register(name, func);
-                    }
-                };
-            }
-            None => (),
-        };
+        self.with_metatable_field("__methods", |methods| {
+            methods.register(name, func);
+        });
         self
     }
+
+    /// Defines a property read as a field, `obj.name` calling `getter` with the object.
+    ///
+    /// The getters are looked up before the methods.
+    pub fn field_getter<P>(&mut self, name: &str, getter: P) -> &mut LuaStruct<T>
+    where
+        P: LuaPush,
+    {
+        self.ensure_properties();
+        self.with_metatable_field("__getters", |getters| {
+            getters.set(name, getter);
+        });
+        self
+    }
+
+    /// Defines a property written as a field, `obj.name = value` calling `setter` with the
+    /// object and the value.
+    ///
+    /// Assigning a field without a setter raises an error.
+    pub fn field_setter<P>(&mut self, name: &str, setter: P) -> &mut LuaStruct<T>
+    where
+        P: LuaPush,
+    {
+        self.ensure_properties();
+        self.with_metatable_field("__setters", |setters| {
+            setters.set(name, setter);
+        });
+        self
+    }
+
+    // the metatable of the objects, popped when dropped
+    fn metatable(&mut self) -> LuaTable {
+        push_metatable::<T>(self.lua);
+        LuaRead::lua_read_with_pop(self.lua, -1, 1).unwrap()
+    }
+
+    // calls `f` with a table of the metatable, creating it if missing
+    fn with_metatable_field<F>(&mut self, field: &str, f: F)
+    where
+        F: FnOnce(&mut LuaTable),
+    {
+        let mut metatable = self.metatable();
+        match metatable.query::<LuaTable, _>(field) {
+            Some(mut field) => f(&mut field),
+            None => f(&mut metatable.empty_table(field)),
+        }
+    }
+
+    // replaces the `__index` methods table by functions dispatching to the properties
+    fn ensure_properties(&mut self) {
+        push_metatable::<T>(self.lua);
+        unsafe {
+            td_clua::lua_getfield(self.lua, -1, GETTERS_KEY.as_ptr() as *const libc::c_char);
+            let ready = td_clua::lua_istable(self.lua, -1);
+            td_clua::lua_pop(self.lua, 1);
+            if !ready {
+                td_clua::lua_newtable(self.lua);
+                td_clua::lua_pushvalue(self.lua, -1);
+                td_clua::lua_setfield(self.lua, -3, GETTERS_KEY.as_ptr() as *const libc::c_char);
+                td_clua::lua_getfield(self.lua, -2, METHODS_KEY.as_ptr() as *const libc::c_char);
+                td_clua::lua_pushcclosure(self.lua, index_dispatch, 2);
+                td_clua::lua_setfield(self.lua, -2, b"__index\0".as_ptr() as *const libc::c_char);
+
+                td_clua::lua_newtable(self.lua);
+                td_clua::lua_pushvalue(self.lua, -1);
+                td_clua::lua_setfield(self.lua, -3, SETTERS_KEY.as_ptr() as *const libc::c_char);
+                td_clua::lua_pushcclosure(self.lua, newindex_dispatch::<T>, 1);
+                td_clua::lua_setfield(self.lua, -2, b"__newindex\0".as_ptr() as *const libc::c_char);
+            }
+            td_clua::lua_pop(self.lua, 1);
+        }
+    }
 }
@@ -27,6 +27,10 @@ macro_rules! integer_impl(
                     _ => Some(val as $t)
                 }
             }
+
+            fn lua_type_name() -> String {
+                "number".to_string()
+            }
         }
     );
 );
@@ -59,6 +63,10 @@ macro_rules! numeric_impl(
                     _ => Some(val as $t)
                 }
             }
+
+            fn lua_type_name() -> String {
+                "number".to_string()
+            }
         }
     );
 );
@@ -83,12 +91,18 @@ impl LuaRead for String {
     fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<String> {
         let mut size = 0;
         let data = unsafe { td_clua::lua_tolstring(lua, index, &mut size) };
+        if data.is_null() {
+            return None;
+        }
         let bytes = unsafe { std::slice::from_raw_parts(data as *const u8, size) };
         match std::str::from_utf8(bytes) {
             Ok(v) => Some(v.to_string()),
             Err(_) => None,
         }
     }
+    fn lua_type_name() -> String {
+        "string".to_string()
+    }
 }
 
 impl<'s> LuaPush for &'s str {
@@ -119,6 +133,9 @@ impl LuaRead for bool {
 
         Some(unsafe { td_clua::lua_toboolean(lua, index) != 0 })
     }
+    fn lua_type_name() -> String {
+        "boolean".to_string()
+    }
 }
 
 impl LuaPush for () {
@@ -159,4 +176,22 @@ impl LuaRead for RawString {
         let value = unsafe { Vec::from_raw_parts(c_str_raw as *mut u8, size, size) };
         Some(RawString(value))
     }
+    fn lua_type_name() -> String {
+        "string".to_string()
+    }
+}
+
+/// Reads nil or a missing value as `None`, so optional arguments can be left out at the end
+/// of a call.
+impl<T> LuaRead for Option<T> where T: LuaRead {
+    fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<Option<T>> {
+        if unsafe { td_clua::lua_isnoneornil(lua, index) } {
+            return Some(None);
+        }
+        T::lua_read_at_position(lua, index).map(Some)
+    }
+
+    fn lua_type_name() -> String {
+        T::lua_type_name()
+    }
 }
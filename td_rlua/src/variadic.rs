@@ -0,0 +1,87 @@
+use std::ops::{Deref, DerefMut};
+
+use libc;
+use td_clua::{self, lua_State};
+
+use functions;
+use LuaPush;
+use LuaRead;
+use LuaValue;
+
+/// Any number of values of the same type.
+///
+/// As the last argument of a Rust function it collects every remaining argument, and
+/// returned by a function it gives each value as a separate result:
+///
+/// ```ignore
+/// lua.set("sum", td_rlua::function1(|values: Variadic<i32>| values.iter().sum::<i32>()));
+/// ```
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Variadic<T>(pub Vec<T>);
+
+/// Any number of values of any type, for the functions taking or returning mixed values.
+///
+/// Taken as the only argument, it holds every argument given to the function, so its length
+/// is the number of arguments, trailing `nil` included.
+pub type MultiValue = Variadic<LuaValue>;
+
+impl<T> Variadic<T> {
+    pub fn new() -> Variadic<T> {
+        Variadic(Vec::new())
+    }
+}
+
+impl<T> From<Vec<T>> for Variadic<T> {
+    fn from(values: Vec<T>) -> Variadic<T> {
+        Variadic(values)
+    }
+}
+
+impl<T> Deref for Variadic<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.0
+    }
+}
+
+impl<T> DerefMut for Variadic<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        &mut self.0
+    }
+}
+
+/// Raises a Lua error when the stack can't grow enough for all the values.
+impl<T> LuaPush for Variadic<T> where T: LuaPush {
+    fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+        let len = self.0.len();
+        if len > libc::c_int::MAX as usize || unsafe { td_clua::lua_checkstack(lua, len as libc::c_int) } == 0 {
+            drop(self);
+            functions::raise_error(lua, format!("stack overflow pushing {} values", len));
+        }
+        self.0.into_iter().map(|value| value.push_to_lua(lua)).sum()
+    }
+}
+
+/// Reads every value from the given position to the top of the stack.
+impl<T> LuaRead for Variadic<T> where T: LuaRead {
+    fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<Variadic<T>> {
+        Self::lua_read_checked(lua, index).ok()
+    }
+
+    fn lua_read_checked(lua: *mut lua_State, index: i32) -> Result<Variadic<T>, (i32, String)> {
+        let index = unsafe { td_clua::lua_absindex(lua, index) };
+        let top = unsafe { td_clua::lua_gettop(lua) };
+        let mut values = Vec::new();
+        for i in index..=top {
+            let value = T::lua_read_checked(lua, i)
+                .map_err(|(offset, expected)| (offset + i - index, expected))?;
+            values.push(value);
+        }
+        Ok(Variadic(values))
+    }
+
+    fn lua_type_name() -> String {
+        T::lua_type_name()
+    }
+}
@@ -0,0 +1,173 @@
+#![cfg(feature = "derive")]
+
+extern crate td_rlua;
+
+use td_rlua::lua_methods;
+use td_rlua::Lua;
+use td_rlua::LuaPush;
+use td_rlua::LuaRead;
+use td_rlua::LuaStruct;
+use td_rlua::NewStruct;
+
+#[derive(Debug, PartialEq, LuaPush, LuaRead)]
+struct Position {
+    x: i32,
+    y: i32,
+    #[lua(rename = "label")]
+    name: String,
+    #[lua(skip)]
+    cached: Option<u32>,
+}
+
+#[derive(Debug, PartialEq, LuaPush, LuaRead)]
+struct Pair(f64, String);
+
+#[derive(Debug, LuaPush, LuaRead)]
+#[lua(userdata)]
+struct Handle {
+    id: u32,
+}
+
+#[test]
+fn table_struct() {
+    let mut lua = Lua::new();
+    lua.set("pos", Position { x: 1, y: 2, name: "start".to_string(), cached: Some(5) });
+
+    let sum: i32 = lua.exec_string("return pos.x + pos.y").unwrap();
+    assert_eq!(sum, 3);
+    let label: String = lua.exec_string("return pos.label").unwrap();
+    assert_eq!(label, "start");
+
+    let _: () = lua.exec_string("pos.x = 10").unwrap();
+    let pos: Position = lua.query("pos").unwrap();
+    assert_eq!(pos, Position { x: 10, y: 2, name: "start".to_string(), cached: None });
+
+    let invalid: Option<Position> = lua.exec_string("return { x = 1, y = 'a', label = '' }");
+    assert!(invalid.is_none());
+    let invalid: Option<Position> = lua.exec_string("return 1");
+    assert!(invalid.is_none());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn tuple_struct() {
+    let mut lua = Lua::new();
+    lua.set("pair", Pair(1.5, "a".to_string()));
+    let len: i32 = lua.exec_string("return #pair").unwrap();
+    assert_eq!(len, 2);
+    let pair: Pair = lua.exec_string("return { 2.5, 'b' }").unwrap();
+    assert_eq!(pair, Pair(2.5, "b".to_string()));
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn userdata_struct() {
+    let mut lua = Lua::new();
+    lua.set("h", Handle { id: 7 });
+    let handle: &mut Handle = lua.query("h").unwrap();
+    assert_eq!(handle.id, 7);
+    handle.id = 8;
+    let handle: &Handle = lua.query("h").unwrap();
+    assert_eq!(handle.id, 8);
+
+    lua.set("p", Pair(0.0, String::new()));
+    let other: Option<&mut Handle> = lua.query("p");
+    assert!(other.is_none());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[derive(LuaPush, LuaRead)]
+#[lua(userdata)]
+struct Player {
+    hp: i32,
+    name: String,
+}
+
+impl NewStruct for Player {
+    fn new() -> Player {
+        Player { hp: 100, name: "nobody".to_string() }
+    }
+
+    fn name() -> &'static str {
+        "Player"
+    }
+}
+
+#[lua_methods]
+impl Player {
+    fn damage(&mut self, amount: i32) -> i32 {
+        self.hp -= amount;
+        self.hp
+    }
+
+    #[lua(name = "greet")]
+    fn greeting(&self, other: String) -> String {
+        format!("{} greets {}", self.name, other)
+    }
+
+    #[lua(getter)]
+    fn hp(&self) -> i32 {
+        self.hp
+    }
+
+    #[lua(setter)]
+    fn set_hp(&mut self, hp: i32) {
+        self.hp = hp;
+    }
+
+    #[lua(getter = "title")]
+    fn display_name(&self) -> String {
+        self.name.clone()
+    }
+
+    #[lua(skip)]
+    fn reset(&mut self) {
+        self.hp = 0;
+    }
+
+    fn create(name: &str) -> Player {
+        Player { hp: 1, name: name.to_string() }
+    }
+}
+
+#[test]
+fn methods() {
+    let mut lua = Lua::new();
+    LuaStruct::<Player>::new(lua.state()).create().def_methods();
+
+    let _: () = lua.exec_string("p = Player()").unwrap();
+    let hp: i32 = lua.exec_string("return p:damage(30)").unwrap();
+    assert_eq!(hp, 70);
+    let greeting: String = lua.exec_string("return p:greet('you')").unwrap();
+    assert_eq!(greeting, "nobody greets you");
+    let hp: i32 = lua.exec_string("p:set_hp(5) return p:get_hp()").unwrap();
+    assert_eq!(hp, 5);
+    let title: String = lua.exec_string("return p:get_title()").unwrap();
+    assert_eq!(title, "nobody");
+    let hp: i32 = lua.exec_string("p.hp = p.hp + 1 return p.hp").unwrap();
+    assert_eq!(hp, 6);
+    let title: String = lua.exec_string("return p.title").unwrap();
+    assert_eq!(title, "nobody");
+    let result: Result<(), _> = lua.try_exec_string("p.title = 'x'");
+    assert!(result.is_err());
+    let hp: i32 = lua.exec_string("p:set_hp(5) return p.hp").unwrap();
+    assert_eq!(hp, 5);
+    let skipped: bool = lua.exec_string("return p.reset == nil and p.create == nil").unwrap();
+    assert!(skipped);
+
+    let player: &mut Player = lua.query("p").unwrap();
+    assert_eq!(player.hp, 5);
+    player.reset();
+    assert_eq!(Player::create("x").hp, 1);
+    assert_eq!(lua.get_top(), 0);
+
+    // an object pushed from Rust has the methods too
+    lua.set("q", Player::create("rust"));
+    let greeting: String = lua.exec_string("return q:greet('lua')").unwrap();
+    assert_eq!(greeting, "rust greets lua");
+    let hp: i32 = lua.exec_string("q.hp = 10 return q:damage(3)").unwrap();
+    assert_eq!(hp, 7);
+    let player: &mut Player = lua.query("q").unwrap();
+    assert_eq!(player.hp, 7);
+    assert_eq!(lua.get_top(), 0);
+}
@@ -0,0 +1,157 @@
+extern crate td_rlua;
+
+use td_rlua::Lua;
+use td_rlua::LuaError;
+
+#[test]
+fn syntax_error() {
+    let mut lua = Lua::new();
+    let val: Result<(), LuaError> = lua.try_exec_string("td_rlua");
+    assert_eq!(lua.get_top(), 0);
+    match val {
+        Err(LuaError::Syntax(_)) => (),
+        _ => panic!("expected a syntax error"),
+    }
+}
+
+#[test]
+fn runtime_error() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let val: Result<(), LuaError> = lua.try_exec_string("error('boom')");
+    assert_eq!(lua.get_top(), 0);
+    match val {
+        Err(LuaError::Runtime { message, .. }) => assert!(message.ends_with("boom")),
+        _ => panic!("expected a runtime error"),
+    }
+}
+
+#[test]
+fn error_object_not_a_string() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let val: Result<(), LuaError> = lua.try_exec_string("error({})");
+    assert_eq!(lua.get_top(), 0);
+    match val {
+        Err(LuaError::Runtime { message, .. }) => assert_eq!(message, "(error object is a table value)"),
+        _ => panic!("expected a runtime error"),
+    }
+}
+
+#[test]
+fn conversion_error() {
+    let mut lua = Lua::new();
+    let val: Result<i32, LuaError> = lua.try_exec_string("return 'hello'");
+    assert_eq!(lua.get_top(), 0);
+    match val {
+        Err(LuaError::Conversion { expected, actual }) => {
+            assert_eq!(expected, "i32");
+            assert_eq!(actual, "string");
+        }
+        _ => panic!("expected a conversion error"),
+    }
+}
+
+#[test]
+fn exec_func_errors() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let _: () = lua.exec_string("function fail(a) error('failed ' .. a) end function add(a, b) return a + b end").unwrap();
+
+    let val = lua.try_exec_func1("fail", 1);
+    assert_eq!(lua.get_top(), 0);
+    match val {
+        Err(LuaError::Runtime { message, .. }) => assert!(message.ends_with("failed 1")),
+        _ => panic!("expected a runtime error"),
+    }
+    assert_eq!(lua.exec_func1("fail", 1), td_rlua::LUA_ERRRUN);
+    assert_eq!(lua.get_top(), 0);
+
+    let val: Result<i32, LuaError> = lua.try_read_func2("add", 1, 2);
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, Ok(3));
+
+    let val: Result<i32, LuaError> = lua.try_read_func0("missing");
+    assert_eq!(lua.get_top(), 0);
+    assert!(val.is_err());
+}
+
+#[test]
+fn custom_error_handler() {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    let errors = Rc::new(RefCell::new(Vec::new()));
+    let mut lua = Lua::new();
+    lua.openlibs();
+    {
+        let errors = errors.clone();
+        lua.set_error_handler(move |msg, traceback| {
+            errors.borrow_mut().push((msg.to_string(), traceback.to_string()));
+        });
+    }
+
+    let _: () = lua.exec_string("function inner() error('deep') end function outer() inner() end").unwrap();
+    let val = lua.try_exec_func0("outer");
+    assert_eq!(lua.get_top(), 0);
+    match val {
+        Err(LuaError::Runtime { traceback: Some(traceback), .. }) => {
+            assert!(traceback.contains("inner"));
+            assert!(traceback.contains("outer"));
+        }
+        _ => panic!("expected a runtime error with a traceback"),
+    }
+
+    {
+        let errors = errors.borrow();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].0.ends_with("deep"));
+        assert!(errors[0].1.starts_with("stack traceback:"));
+    }
+
+    // the handler is not reachable from scripts
+    let handler: Option<()> = lua.exec_string("assert(error_handle == nil)");
+    assert!(handler.is_some());
+
+    lua.clear_error_handler();
+    let val: Option<()> = lua.exec_string("error('ignored')");
+    assert!(val.is_none());
+    assert_eq!(errors.borrow().len(), 1);
+}
+
+#[test]
+fn stack_frames() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set_capture_locals(true);
+    let code = r"
+        function inner(value)
+            local doubled = value * 2
+            error('deep')
+        end
+
+        function outer()
+            inner(21)
+        end
+    ";
+    let _: () = lua.exec_string(code).unwrap();
+    let val = lua.try_exec_func0("outer");
+    assert_eq!(lua.get_top(), 0);
+    let frames = match val {
+        Err(LuaError::Runtime { frames, .. }) => frames,
+        _ => panic!("expected a runtime error"),
+    };
+
+    let inner = frames.iter().find(|f| f.name == Some("inner".to_string())).unwrap();
+    assert_eq!(inner.what, "Lua");
+    assert_eq!(inner.namewhat, "global");
+    assert_eq!(inner.line, Some(4));
+    assert_eq!(inner.source, "[string \"...\"]");
+    assert_eq!(inner.locals, vec![("value".to_string(), "21".to_string()),
+                                  ("doubled".to_string(), "42".to_string())]);
+
+    // called from Rust, so Lua can't find a name for it
+    let outer = frames.iter().find(|f| f.line == Some(8)).unwrap();
+    assert_eq!(outer.name, None);
+    assert!(outer.locals.is_empty());
+}
@@ -125,7 +125,7 @@ fn closures() {
 
 #[test]
 fn closures_lifetime() {
-    fn t<F>(f: F) where F: Fn(i32, i32) -> i32 {
+    fn t<F>(f: F) where F: Fn(i32, i32) -> i32 + 'static {
         let mut lua = Lua::new();
 
         lua.set("add", td_rlua::function2(f));
@@ -140,39 +140,48 @@ fn closures_lifetime() {
 
 #[test]
 fn closures_extern_access() {
-    let mut a = 5;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    let a = Rc::new(Cell::new(5));
 
     {
         let mut lua = Lua::new();
 
-        lua.set("inc", td_rlua::function0(|| a += 1));
+        let inc = a.clone();
+        lua.set("inc", td_rlua::function0(move || inc.set(inc.get() + 1)));
         for _ in 0 .. 15 {
             let _: () = lua.exec_string("inc()").unwrap();
             assert_eq!(lua.get_top(), 0);
         }
     }
 
-    assert_eq!(a, 20)
+    assert_eq!(a.get(), 20)
 }
 
 #[test]
 fn test_exec_func() {
+    use std::cell::Cell;
+    use std::rc::Rc;
+
     let mut lua = Lua::new();
     {
-        let mut index = 5;
-        lua.set("add", td_rlua::function1(|a:i32| index += a));
+        let index = Rc::new(Cell::new(5));
+        let add = index.clone();
+        lua.set("add", td_rlua::function1(move |a:i32| add.set(add.get() + a)));
         let success: i32 = lua.exec_func1("add", 3);
         assert_eq!(lua.get_top(), 0);
         assert!(success == 0);
-        assert_eq!(index, 8);
+        assert_eq!(index.get(), 8);
     }
     {
-        let mut index = 5;
-        lua.set("sub", td_rlua::function3(|a:i32, b:u32, _c : String| index -= a + b as i32));
+        let index = Rc::new(Cell::new(5));
+        let sub = index.clone();
+        lua.set("sub", td_rlua::function3(move |a:i32, b:u32, _c : String| sub.set(sub.get() - (a + b as i32))));
         let success: i32 = lua.exec_func3("sub", 3, 1, "".to_string());
         assert_eq!(lua.get_top(), 0);
         assert!(success == 0);
-        assert_eq!(index, 1);
+        assert_eq!(index.get(), 1);
     }
 }
 
@@ -208,4 +217,315 @@ fn test_exec_func_by_param() {
     let ret: Option<i32> = lua.exec_string("return test();");
     assert_eq!(lua.get_top(), 0);
     assert_eq!(ret.unwrap(), 6);
-}
\ No newline at end of file
+}
+#[test]
+fn test_call() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let func = r"
+        function sum(...)
+            local sum = 0
+            for _,v in ipairs({...}) do
+                sum = sum + v
+            end
+            return sum
+        end
+
+        function div_mod(a, b)
+            return a // b, a % b
+        end
+
+        function nothing() end
+
+        game = { util = { add = function(a, b) return a + b end } }
+        player = { hp = 10 }
+        function player:hit(damage)
+            self.hp = self.hp - damage
+            return self.hp
+        end
+    ";
+    let _: () = lua.exec_string(func).unwrap();
+
+    let val: i32 = lua.call("sum", (1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12)).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, 78);
+
+    let val: (i32, i32) = lua.call("div_mod", (17, 5)).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, (3, 2));
+
+    let val: i32 = lua.call("div_mod", (17, 5)).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, 3);
+
+    let val: () = lua.call("nothing", ()).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, ());
+
+    let val = lua.call::<i32>("nothing", ());
+    assert_eq!(lua.get_top(), 0);
+    assert!(val.is_err());
+
+    let args: Vec<i32> = (1..=20).collect();
+    let val: i32 = lua.call("sum", td_rlua::Variadic(args)).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, 210);
+
+    let args = vec![td_rlua::LuaValue::Integer(17), td_rlua::LuaValue::Number(5.0)];
+    let val: (i32, i32) = lua.call("div_mod", td_rlua::MultiValue::from(args)).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, (3, 2));
+
+    let args: Vec<td_rlua::LuaValue> = (1..=20).map(td_rlua::LuaValue::Integer).collect();
+    let val: i32 = lua.call_values("sum", args).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, 210);
+
+    let args = vec![td_rlua::LuaValue::Integer(17), td_rlua::LuaValue::Integer(5)];
+    let val: (i32, i32) = lua.call_values("div_mod", args).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, (3, 2));
+
+    let val: i32 = lua.call("game.util.add", (1, 2)).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, 3);
+
+    let val: i32 = lua.call("player:hit", 3).unwrap();
+    assert_eq!(lua.get_top(), 0);
+    assert_eq!(val, 7);
+
+    let val = lua.call::<i32>("game.missing.add", (1, 2));
+    assert_eq!(lua.get_top(), 0);
+    assert!(val.is_err());
+}
+
+#[test]
+fn closures_dropped() {
+    use std::rc::Rc;
+
+    let captured = Rc::new(5);
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let value = captured.clone();
+    lua.set("get", td_rlua::function0(move || *value));
+    let value = captured.clone();
+    lua.set("shared_get", td_rlua::shared_function0(move || *value));
+    assert_eq!(Rc::strong_count(&captured), 3);
+
+    let val: i32 = lua.exec_string("return get() + shared_get()").unwrap();
+    assert_eq!(val, 10);
+    let _: () = lua.exec_string("get = nil collectgarbage()").unwrap();
+    assert_eq!(Rc::strong_count(&captured), 2);
+
+    drop(lua);
+    assert_eq!(Rc::strong_count(&captured), 1);
+}
+
+#[test]
+fn reentrant_calls() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let state = lua.state();
+
+    // a FnMut calling itself through Lua would alias its state
+    let mut calls = 0;
+    lua.set("count", td_rlua::function1(move |again: bool| {
+        calls += 1;
+        if again {
+            let mut lua = Lua::from_existing_state(state, false);
+            let inner: Result<i32, td_rlua::LuaError> = lua.try_exec_string("return count(false)");
+            match inner {
+                Err(td_rlua::LuaError::Runtime { message, .. }) => assert!(message.contains("already running")),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+        calls
+    }));
+    let val: i32 = lua.exec_string("return count(true)").unwrap();
+    assert_eq!(val, 1);
+    let val: i32 = lua.exec_string("return count(false)").unwrap();
+    assert_eq!(val, 2);
+
+    // a Fn can be called again while running
+    lua.set("fact", td_rlua::shared_function1(move |n: u32| -> u32 {
+        if n <= 1 {
+            return 1;
+        }
+        let mut lua = Lua::from_existing_state(state, false);
+        let below: u32 = lua.call("fact", n - 1).unwrap();
+        n * below
+    }));
+    let val: u32 = lua.exec_string("return fact(5)").unwrap();
+    assert_eq!(val, 120);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn errors_release_functions() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+
+    // a FnMut failing can be called again
+    let mut calls = 0;
+    lua.set("fail", td_rlua::function1(move |how: String| -> Result<i32, String> {
+        calls += 1;
+        match &*how {
+            "error" => Err("failed".to_string()),
+            "panic" => panic!("failed"),
+            _ => Ok(calls),
+        }
+    }));
+    assert!(lua.try_exec_string::<_, i32>("return fail('error')").is_err());
+    assert!(lua.try_exec_string::<_, i32>("return fail('panic')").is_err());
+    assert!(lua.try_exec_string::<_, i32>("return fail()").is_err());
+    let val: i32 = lua.exec_string("return fail('ok')").unwrap();
+    assert_eq!(val, 3);
+
+    // even when the error is raised by Lua while the function runs
+    let mut calls = 0;
+    lua.set("index", td_rlua::function1(move |mut table: td_rlua::LuaTable| -> i32 {
+        calls += 1;
+        table.query::<i32, _>("x").unwrap_or(0) + calls
+    }));
+    let _: () = lua.exec_string("failing = setmetatable({}, { __index = function() error('no x') end })").unwrap();
+    let err = lua.try_exec_string::<_, i32>("return index(failing)").unwrap_err();
+    assert!(err.to_string().contains("no x"), "{}", err);
+    let val: i32 = lua.exec_string("return index({ x = 10 })").unwrap();
+    assert_eq!(val, 12);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn result_errors() {
+    use std::fmt;
+    use std::rc::Rc;
+    use td_rlua::ArgumentError;
+
+    // counts the live errors, to check the error is dropped before raising it
+    struct Tracked {
+        _live: Rc<()>,
+    }
+
+    impl fmt::Display for Tracked {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "tracked failure")
+        }
+    }
+
+    let live = Rc::new(());
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let tracked = live.clone();
+    lua.set("fail", td_rlua::function1(move |fail: bool| -> Result<i32, Tracked> {
+        if fail {
+            return Err(Tracked { _live: tracked.clone() });
+        }
+        Ok(1)
+    }));
+    let val: i32 = lua.exec_string("return fail(false)").unwrap();
+    assert_eq!(val, 1);
+    let message: String = lua.exec_string("local ok, err = pcall(fail, true) return err").unwrap();
+    assert_eq!(message, "tracked failure");
+    assert_eq!(Rc::strong_count(&live), 2);
+
+    lua.set("foo", td_rlua::function2(|_: i32, b: td_rlua::LuaValue| -> Result<(), ArgumentError> {
+        match b.type_name() {
+            "number" => Ok(()),
+            actual => Err(ArgumentError::type_mismatch(2, "number", actual).function("foo")),
+        }
+    }));
+    match lua.try_exec_string::<_, ()>("foo(1, 'x')") {
+        Err(td_rlua::LuaError::Runtime { message, .. }) => {
+            assert_eq!(message, "bad argument #2 to 'foo' (number expected, got string)")
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(ArgumentError::new(1, "out of range").to_string(), "bad argument #1 (out of range)");
+}
+
+#[test]
+fn argument_errors() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    fn add(val1: i32, val2: i32) -> i32 { val1 + val2 }
+    lua.set("add", td_rlua::function2(add));
+
+    let mut check = |code: &str, expected: &str| {
+        match lua.try_exec_string::<_, ()>(code) {
+            Err(td_rlua::LuaError::Runtime { message, .. }) => assert_eq!(message, expected),
+            other => panic!("unexpected {:?}", other),
+        }
+    };
+    check("add(3, 'hello')", "bad argument #2 to 'add' (number expected, got string)");
+    check("add(3)", "bad argument #2 to 'add' (number expected, got no value)");
+    check("local sum = add sum({}, 1)", "bad argument #1 to 'sum' (number expected, got table)");
+    check("local t = { add = add } t:add(1)", "calling 'add' on bad self (number expected, got table)");
+
+    // optional arguments may be nil or left out at the end
+    lua.set("inc", td_rlua::function2(|val: i32, step: Option<i32>| val + step.unwrap_or(1)));
+    let val: i32 = lua.exec_string("return inc(1) + inc(1, nil) + inc(1, 5)").unwrap();
+    assert_eq!(val, 10);
+    let result: Result<i32, _> = lua.try_exec_string("return inc(1, true)");
+    match result {
+        Err(td_rlua::LuaError::Runtime { message, .. }) => {
+            assert_eq!(message, "bad argument #2 to 'inc' (number expected, got boolean)")
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+#[test]
+fn variadic_functions() {
+    use td_rlua::{LuaError, LuaValue, MultiValue, Variadic};
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set("sum", td_rlua::function2(|first: i32, others: Variadic<i32>| {
+        first + others.iter().sum::<i32>()
+    }));
+    let val: i32 = lua.exec_string("return sum(1) + sum(1, 2, 3)").unwrap();
+    assert_eq!(val, 7);
+    match lua.try_exec_string::<_, ()>("sum(1, 2, 'x')") {
+        Err(td_rlua::LuaError::Runtime { message, .. }) => {
+            assert_eq!(message, "bad argument #3 to 'sum' (number expected, got string)")
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    // every argument is kept, trailing nils included
+    lua.set("count", td_rlua::function1(|args: MultiValue| args.len()));
+    let val: usize = lua.exec_string("return count(1, nil, 'a', nil)").unwrap();
+    assert_eq!(val, 4);
+    let val: usize = lua.exec_string("return count()").unwrap();
+    assert_eq!(val, 0);
+
+    lua.set("split", td_rlua::function1(|text: String| -> Variadic<String> {
+        Variadic(text.split(',').map(|part| part.to_string()).collect())
+    }));
+    let val: String = lua.exec_string("local a, b, c = split('x,y,z') return c .. b .. a").unwrap();
+    assert_eq!(val, "zyx");
+
+    lua.set("mixed", td_rlua::function0(|| {
+        Variadic(vec![LuaValue::Integer(1), LuaValue::Boolean(true), LuaValue::Nil])
+    }));
+    let val: String = lua.exec_string("return table.concat({ type(mixed()), select('#', mixed()) }, ' ')").unwrap();
+    assert_eq!(val, "number 3");
+
+    lua.set("many", td_rlua::function0(|| Variadic((0..100000).collect::<Vec<i32>>())));
+    let val: i32 = lua.exec_string("return select('#', many())").unwrap();
+    assert_eq!(val, 100000);
+    let val: i32 = lua.exec_string("return select(-1, many())").unwrap();
+    assert_eq!(val, 99999);
+
+    let args: Vec<LuaValue> = (0..100000).map(LuaValue::Integer).collect();
+    let val: i32 = lua.call("select", ("#", MultiValue::from(args))).unwrap();
+    assert_eq!(val, 100000);
+
+    lua.set("too_many", td_rlua::function0(|| Variadic(vec![0u8; 2000000])));
+    let val: Result<(), LuaError> = lua.try_exec_string("too_many()");
+    match val {
+        Err(LuaError::Runtime { message, .. }) => assert!(message.contains("stack overflow")),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(lua.get_top(), 0);
+}
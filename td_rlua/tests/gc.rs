@@ -0,0 +1,47 @@
+extern crate td_rlua;
+
+use td_rlua::GcMode;
+use td_rlua::Lua;
+
+#[test]
+fn stop_and_step() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    assert!(lua.gc().is_running());
+
+    lua.gc().stop();
+    assert!(!lua.gc().is_running());
+    let before = lua.gc().bytes();
+    let _: () = lua.exec_string("for i = 1, 10000 do local t = { i } end").unwrap();
+    let grown = lua.gc().bytes();
+    assert!(grown > before + 100 * 1024);
+    assert_eq!(lua.gc().kbytes(), grown / 1024);
+
+    // stepping still works while stopped, until a cycle is finished
+    let mut steps = 0;
+    while !lua.gc().step(16) {
+        steps += 1;
+        assert!(steps < 10000);
+    }
+    assert!(lua.gc().bytes() < grown);
+    assert!(!lua.gc().is_running());
+
+    lua.gc().restart();
+    assert!(lua.gc().is_running());
+    let _: () = lua.exec_string("for i = 1, 10000 do local t = { i } end").unwrap();
+    lua.gc().collect();
+    assert!(lua.gc().bytes() < grown);
+}
+
+#[test]
+fn tuning() {
+    let mut lua = Lua::new();
+    assert_eq!(lua.gc().set_pause(150), 200);
+    assert_eq!(lua.gc().set_pause(200), 150);
+    assert_eq!(lua.gc().set_step_multiplier(400), 200);
+    assert_eq!(lua.gc().set_step_multiplier(200), 400);
+
+    assert!(lua.gc().set_mode(GcMode::Incremental));
+    // Lua 5.3 has no generational collector
+    assert!(!lua.gc().set_mode(GcMode::Generational));
+}
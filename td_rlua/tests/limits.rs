@@ -0,0 +1,95 @@
+extern crate td_rlua;
+
+use std::time::{Duration, Instant};
+
+use td_rlua::Lua;
+use td_rlua::LuaError;
+use td_rlua::LuaFunctionRef;
+use td_rlua::LuaThread;
+
+#[test]
+fn instruction_limit() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set_instruction_limit(10_000);
+
+    let sum: i32 = lua.exec_string("local s = 0 for i = 1, 10 do s = s + i end return s").unwrap();
+    assert_eq!(sum, 55);
+
+    let result: Result<(), LuaError> = lua.try_exec_string("while true do end");
+    assert_eq!(result, Err(LuaError::InstructionLimit));
+    assert!(lua.instruction_count() >= 10_000);
+
+    // the budget is spent until reset, even for a script catching the error
+    let result: Result<(), LuaError> = lua.try_exec_string("for i = 1, 2000 do end");
+    assert_eq!(result, Err(LuaError::InstructionLimit));
+    lua.reset_limits();
+    let result: Result<(), LuaError> = lua.try_exec_string("while true do pcall(function() while true do end end) end");
+    assert_eq!(result, Err(LuaError::InstructionLimit));
+
+    lua.remove_limits();
+    let sum: i32 = lua.exec_string("local s = 0 for i = 1, 100000 do s = s + 1 end return s").unwrap();
+    assert_eq!(sum, 100_000);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn timeout() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let _: () = lua.exec_string("function spin() while true do end end").unwrap();
+
+    lua.set_timeout(Duration::from_millis(50));
+    let start = Instant::now();
+    let result: Result<(), LuaError> = lua.call("spin", ());
+    assert_eq!(result, Err(LuaError::Timeout));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(LuaError::Timeout.to_string(), "execution timed out");
+
+    lua.reset_limits();
+    let value: i32 = lua.exec_string("return 7").unwrap();
+    assert_eq!(value, 7);
+
+    // coroutines created afterwards are limited as well
+    let result: Result<(), LuaError> = lua.try_exec_string("coroutine.wrap(spin)()");
+    assert_eq!(result, Err(LuaError::Timeout));
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn existing_coroutines() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let _: () = lua.exec_string(r"
+        function spin() while true do end end
+        co = coroutine.create(spin)
+        wrapped = coroutine.wrap(spin)
+    ").unwrap();
+    let spin: LuaFunctionRef = lua.query("spin").unwrap();
+    let mut thread = LuaThread::new(&spin);
+
+    // coroutines created before the limits are limited once resumed
+    lua.set_timeout(Duration::from_millis(20));
+    let result: Result<(), LuaError> = lua.try_exec_string("assert(coroutine.resume(co))");
+    assert_eq!(result, Err(LuaError::Timeout));
+    lua.reset_limits();
+    let result: Result<(), LuaError> = lua.try_exec_string("wrapped()");
+    assert_eq!(result, Err(LuaError::Timeout));
+    lua.reset_limits();
+    assert_eq!(thread.resume::<()>(()).map(|_| ()), Err(LuaError::Timeout));
+
+    // and the hook of a suspended coroutine follows the limits when it is resumed
+    let _: () = lua.exec_string("ticker = coroutine.create(function() while true do coroutine.yield() end end)").unwrap();
+    let hook_count = "assert(coroutine.resume(ticker)) return select(3, debug.gethook(ticker))";
+    lua.set_instruction_limit(500);
+    let result: Result<(), LuaError> = lua.try_exec_string("while true do coroutine.resume(ticker) end");
+    assert_eq!(result, Err(LuaError::InstructionLimit));
+    lua.reset_limits();
+    let count: i32 = lua.exec_string(hook_count).unwrap();
+    assert_eq!(count, 500);
+    lua.remove_limits();
+    let count: i32 = lua.exec_string(hook_count).unwrap();
+    assert_eq!(count, 0);
+    assert_eq!(lua.get_top(), 0);
+}
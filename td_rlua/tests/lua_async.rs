@@ -0,0 +1,166 @@
+#![cfg(feature = "async")]
+
+extern crate td_rlua;
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::task::{Context, Poll, Wake, Waker};
+
+use td_rlua::Lua;
+use td_rlua::LuaError;
+
+// a minimal local executor counting the wake ups
+struct Counter(AtomicUsize);
+
+impl Wake for Counter {
+    fn wake(self: Arc<Self>) {
+        self.0.fetch_add(1, Ordering::SeqCst);
+    }
+}
+
+fn poll_once<F: Future + Unpin>(future: &mut F, counter: &Arc<Counter>) -> Poll<F::Output> {
+    let waker = Waker::from(counter.clone());
+    let mut cx = Context::from_waker(&waker);
+    Pin::new(future).poll(&mut cx)
+}
+
+fn block_on<F: Future + Unpin>(mut future: F) -> (F::Output, usize) {
+    let counter = Arc::new(Counter(AtomicUsize::new(0)));
+    let mut polls = 0;
+    loop {
+        polls += 1;
+        if let Poll::Ready(output) = poll_once(&mut future, &counter) {
+            return (output, polls);
+        }
+        assert_eq!(counter.0.load(Ordering::SeqCst), polls, "polled without wake up");
+    }
+}
+
+// ready once polled `polls` more times
+struct Delay {
+    polls: u32,
+    value: u32,
+}
+
+impl Future for Delay {
+    type Output = u32;
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<u32> {
+        if self.polls == 0 {
+            return Poll::Ready(self.value);
+        }
+        self.polls -= 1;
+        cx.waker().wake_by_ref();
+        Poll::Pending
+    }
+}
+
+// ready once the test fills the slot
+struct Slot(Rc<RefCell<Option<String>>>);
+
+impl Future for Slot {
+    type Output = String;
+
+    fn poll(self: Pin<&mut Self>, _cx: &mut Context) -> Poll<String> {
+        match self.0.borrow_mut().take() {
+            Some(value) => Poll::Ready(value),
+            None => Poll::Pending,
+        }
+    }
+}
+
+#[test]
+fn call_async() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set("sleep", td_rlua::async_function1(|polls: u32| Delay { polls, value: polls * 2 }));
+    let _: () = lua.exec_string(r"
+        function run(a, b)
+            return sleep(a) + sleep(b), 'done'
+        end
+    ").unwrap();
+
+    let (result, polls) = block_on(lua.call_async::<(u32, String)>("run", (2, 3)));
+    let (total, done) = result.unwrap();
+    assert_eq!(total, 10);
+    assert_eq!(done, "done");
+    assert_eq!(polls, 6);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn suspended_while_pending() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let slot = Rc::new(RefCell::new(None));
+    let shared = slot.clone();
+    lua.set("recv", td_rlua::async_function0(move || Slot(shared.clone())));
+    let _: () = lua.exec_string(r"
+        log = {}
+        function run()
+            log[#log + 1] = 'start'
+            local msg = recv()
+            log[#log + 1] = msg
+            coroutine.yield()
+            log[#log + 1] = 'end'
+            return msg .. '!'
+        end
+    ").unwrap();
+
+    let counter = Arc::new(Counter(AtomicUsize::new(0)));
+    let mut call = lua.call_async::<String>("run", ());
+    assert!(poll_once(&mut call, &counter).is_pending());
+    assert!(poll_once(&mut call, &counter).is_pending());
+    assert_eq!(counter.0.load(Ordering::SeqCst), 0);
+
+    *slot.borrow_mut() = Some("hello".to_string());
+    // a plain yield hands control back to the executor
+    assert!(poll_once(&mut call, &counter).is_pending());
+    assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+    match poll_once(&mut call, &counter) {
+        Poll::Ready(result) => assert_eq!(result.unwrap(), "hello!"),
+        Poll::Pending => panic!("still pending"),
+    }
+    let log: String = lua.exec_string("return table.concat(log, ',')").unwrap();
+    assert_eq!(log, "start,hello,end");
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn errors() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set("sleep", td_rlua::async_function1(|polls: u32| Delay { polls, value: polls }));
+    let _: () = lua.exec_string(r"
+        function fail()
+            sleep(1)
+            error('after sleep')
+        end
+    ").unwrap();
+
+    let (result, _) = block_on(lua.call_async::<()>("fail", ()));
+    match result {
+        Err(LuaError::Runtime { message, traceback, .. }) => {
+            assert!(message.contains("after sleep"));
+            assert!(traceback.unwrap().contains("fail"));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+
+    let (missing, _) = block_on(lua.call_async::<()>("missing", ()));
+    assert!(missing.is_err());
+    let (wrong, _) = block_on(lua.call_async::<()>("sleep", "x"));
+    assert!(wrong.is_err());
+
+    // outside of call_async there is no coroutine to suspend
+    let result: Result<(), LuaError> = lua.try_exec_string("sleep(1)");
+    match result {
+        Err(LuaError::Runtime { message, .. }) => assert!(message.contains("outside a coroutine")),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(lua.get_top(), 0);
+}
@@ -0,0 +1,132 @@
+extern crate td_rlua;
+
+use td_rlua::Lua;
+use td_rlua::LuaFunctionRef;
+use td_rlua::LuaRef;
+use td_rlua::LuaTableRef;
+use td_rlua::LuaThread;
+use td_rlua::ThreadStatus;
+use td_rlua::LuaValue;
+
+struct EventBus {
+    listeners: Vec<LuaFunctionRef>,
+}
+
+impl EventBus {
+    fn fire(&self, event: &str) -> Vec<i32> {
+        self.listeners.iter().map(|f| f.call(event).unwrap()).collect()
+    }
+}
+
+#[test]
+fn stored_callbacks() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let _: () = lua.exec_string(r"
+        count = 0
+        function on_event(name) count = count + 1 return #name end
+        function on_other(name) return count * 100 end
+    ").unwrap();
+
+    let mut bus = EventBus { listeners: vec![] };
+    bus.listeners.push(lua.query("on_event").unwrap());
+    bus.listeners.push(lua.query("on_other").unwrap());
+    assert_eq!(lua.get_top(), 0);
+
+    // the functions don't depend on the globals anymore
+    let _: () = lua.exec_string("on_event = nil on_other = nil").unwrap();
+    let _: () = lua.exec_string("collectgarbage()").unwrap();
+
+    assert_eq!(bus.fire("hello"), vec![5, 100]);
+    assert_eq!(bus.fire("hi"), vec![2, 200]);
+    assert_eq!(lua.get_top(), 0);
+
+    let cloned = bus.listeners[0].clone();
+    assert_eq!(cloned, bus.listeners[0]);
+    assert!(cloned != bus.listeners[1]);
+    drop(bus);
+    assert_eq!(cloned.call::<i32>("abc").unwrap(), 3);
+}
+
+#[test]
+fn call_errors() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let f: LuaFunctionRef = lua.exec_string("return function(a) return a.b end").unwrap();
+    assert!(f.call::<i32>(()).is_err());
+    assert_eq!(lua.get_top(), 0);
+
+    let not_function: Option<LuaFunctionRef> = lua.exec_string("return 1");
+    assert!(not_function.is_none());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn released_on_drop() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let _: () = lua.exec_string("weak = setmetatable({}, { __mode = 'v' }) weak[1] = {}").unwrap();
+
+    let table: LuaRef = lua.exec_string("return weak[1]").unwrap();
+    let _: () = lua.exec_string("collectgarbage()").unwrap();
+    let alive: bool = lua.exec_string("return weak[1] ~= nil").unwrap();
+    assert!(alive);
+
+    drop(table);
+    let _: () = lua.exec_string("collectgarbage()").unwrap();
+    let alive: bool = lua.exec_string("return weak[1] ~= nil").unwrap();
+    assert!(!alive);
+}
+
+#[test]
+fn table_ref() {
+    let mut lua = Lua::new();
+    let table = unsafe { LuaTableRef::new(lua.state()) };
+    table.set("a", 5);
+    table.set(1, "first");
+    assert_eq!(table.query::<i32, _>("a"), Some(5));
+    assert_eq!(table.query::<String, _>(1), Some("first".to_string()));
+    assert_eq!(table.table_len(), 1);
+    assert_eq!(lua.get_top(), 0);
+
+    lua.set("t", &table);
+    let val: i32 = lua.exec_string("return t.a").unwrap();
+    assert_eq!(val, 5);
+
+    let value: LuaValue = lua.query("t").unwrap();
+    assert_eq!(value.get("a"), Some(&LuaValue::Integer(5)));
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn outlive_state() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let f: LuaFunctionRef = lua.exec_string("return function() return 1 end").unwrap();
+    let table: LuaTableRef = lua.exec_string("return { 1, 2 }").unwrap();
+    let value: LuaValue = lua.exec_string("return function() end").unwrap();
+    let mut thread = LuaThread::new(&f);
+    let cloned = f.clone();
+    assert!(!f.as_lua_ref().is_closed());
+    drop(lua);
+
+    assert!(f.as_lua_ref().is_closed());
+    assert!(f.call::<i32>(()).is_err());
+    assert_eq!(table.query::<i32, _>(1), None);
+    assert!(cloned != f);
+    assert!(f.clone().as_lua_ref().is_closed());
+    assert_eq!(thread.status(), ThreadStatus::Error);
+    assert!(thread.resume::<i32>(()).is_err());
+    drop(value);
+    drop(cloned);
+    drop(table);
+    drop(thread);
+    drop(f);
+
+    // a reference owned by the state itself is released while the state closes
+    let mut lua = Lua::new();
+    let f: LuaFunctionRef = lua.exec_string("return function() return 1 end").unwrap();
+    lua.set("call", td_rlua::function0(move || f.call::<i32>(()).unwrap()));
+    let val: i32 = lua.exec_string("return call()").unwrap();
+    assert_eq!(val, 1);
+}
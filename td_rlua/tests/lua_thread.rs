@@ -0,0 +1,145 @@
+extern crate td_rlua;
+
+use td_rlua::Lua;
+use td_rlua::LuaError;
+use td_rlua::LuaFunctionRef;
+use td_rlua::LuaThread;
+use td_rlua::Resume;
+use td_rlua::ThreadStatus;
+
+#[test]
+fn resume_and_yield() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let _: () = lua.exec_string(r"
+        function quest(name)
+            local answer = coroutine.yield('hello ' .. name, 1)
+            local total = 0
+            for i = 1, 3 do
+                total = total + coroutine.yield(answer .. i)
+            end
+            return 'done', total
+        end
+    ").unwrap();
+
+    let quest: LuaFunctionRef = lua.query("quest").unwrap();
+    let mut thread = LuaThread::new(&quest);
+    assert_eq!(thread.status(), ThreadStatus::Suspended);
+
+    let first: Resume<(String, i32)> = thread.resume("bob").unwrap();
+    assert_eq!(first, Resume::Yield(("hello bob".to_string(), 1)));
+    assert_eq!(thread.status(), ThreadStatus::Suspended);
+
+    let step: Resume<String> = thread.resume("step").unwrap();
+    assert_eq!(step, Resume::Yield("step1".to_string()));
+    let _: Resume<String> = thread.resume(10).unwrap();
+    let _: Resume<String> = thread.resume(20).unwrap();
+    let last: Resume<(String, i32)> = thread.resume(30).unwrap();
+    assert!(last.is_finished());
+    assert_eq!(last.into_inner(), ("done".to_string(), 60));
+    assert_eq!(thread.status(), ThreadStatus::Finished);
+
+    let dead: Result<Resume<()>, LuaError> = thread.resume(());
+    assert!(dead.is_err());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn errors() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut co: LuaThread = lua.exec_string(r"
+        return coroutine.create(function()
+            coroutine.yield()
+            local t = nil
+            return t.field
+        end)
+    ").unwrap();
+    let _: Resume<()> = co.resume(()).unwrap();
+    match co.resume::<()>(()) {
+        Err(LuaError::Runtime { message, traceback, frames }) => {
+            assert!(message.contains("attempt to index"));
+            assert!(traceback.unwrap().contains("stack traceback"));
+            assert_eq!(frames[0].line, Some(5));
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(co.status(), ThreadStatus::Error);
+    assert!(co.resume::<()>(()).is_err());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn shared_with_lua() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let _: () = lua.exec_string("function gen() for i = 1, 2 do coroutine.yield(i) end end").unwrap();
+    let gen: LuaFunctionRef = lua.query("gen").unwrap();
+    let mut thread = LuaThread::new(&gen);
+    let one: Resume<i32> = thread.resume(()).unwrap();
+    assert_eq!(one, Resume::Yield(1));
+
+    // the same coroutine can be resumed from Lua
+    lua.set("co", &thread);
+    let (ok, two): (bool, i32) = lua.call("coroutine.resume", &thread).unwrap();
+    assert!(ok);
+    assert_eq!(two, 2);
+    let status: String = lua.exec_string("return coroutine.status(co)").unwrap();
+    assert_eq!(status, "suspended");
+    let finished: Resume<()> = thread.resume(()).unwrap();
+    assert!(finished.is_finished());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn yielding_callback() {
+    use td_rlua::Outcome;
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    // wait(0) returns immediately, otherwise the coroutine is suspended until resumed
+    lua.set("wait", td_rlua::function1(|seconds: u32| {
+        if seconds == 0 {
+            Outcome::Return("now")
+        } else {
+            Outcome::Yield(seconds)
+        }
+    }));
+    let _: () = lua.exec_string(r"
+        function script()
+            local log = { wait(0) }
+            for i = 1, 2 do
+                local woke = wait(i * 10)
+                log[#log + 1] = woke
+            end
+            return table.concat(log, ',')
+        end
+    ").unwrap();
+
+    let script: LuaFunctionRef = lua.query("script").unwrap();
+    let mut thread = LuaThread::new(&script);
+    let mut clock = 0;
+    let mut wakes = vec![];
+    loop {
+        match thread.resume::<td_rlua::LuaValue>(format!("t{}", clock)).unwrap() {
+            Resume::Yield(seconds) => {
+                let seconds = seconds.as_integer().unwrap();
+                wakes.push(seconds);
+                clock += seconds;
+            }
+            Resume::Finished(log) => {
+                assert_eq!(log.as_str(), Some("now,t10,t30"));
+                break;
+            }
+        }
+    }
+    assert_eq!(wakes, vec![10, 20]);
+
+    // yielding from the main thread is an error
+    let result: Result<(), LuaError> = lua.try_exec_string("wait(1)");
+    match result {
+        Err(LuaError::Runtime { message, .. }) => assert!(message.contains("outside a coroutine")),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(lua.get_top(), 0);
+}
@@ -0,0 +1,117 @@
+extern crate td_rlua;
+
+use td_rlua::Lua;
+use td_rlua::LuaValue;
+
+#[test]
+fn read_basic_values() {
+    let mut lua = Lua::new();
+    let _: () = lua.exec_string("a = nil b = true c = 5 d = 1.5 e = 'hello'").unwrap();
+
+    let a: LuaValue = lua.query("a").unwrap();
+    assert_eq!(a, LuaValue::Nil);
+    let b: LuaValue = lua.query("b").unwrap();
+    assert_eq!(b, LuaValue::Boolean(true));
+    let c: LuaValue = lua.query("c").unwrap();
+    assert_eq!(c, LuaValue::Integer(5));
+    let d: LuaValue = lua.query("d").unwrap();
+    assert_eq!(d, LuaValue::Number(1.5));
+    let e: LuaValue = lua.query("e").unwrap();
+    assert_eq!(e.as_str(), Some("hello"));
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn read_config_table() {
+    let mut lua = Lua::new();
+    let config: LuaValue = lua.exec_string("return { name = 'server', port = 8080, tags = { 'a', 'b' } }").unwrap();
+    assert_eq!(lua.get_top(), 0);
+
+    assert_eq!(config.type_name(), "table");
+    assert_eq!(config.get("name").and_then(|v| v.as_str()), Some("server"));
+    assert_eq!(config.get("port").and_then(|v| v.as_integer()), Some(8080));
+    let tags = config.get("tags").unwrap();
+    assert_eq!(tags.get(1).and_then(|v| v.as_str()), Some("a"));
+    assert_eq!(tags.get(2.0).and_then(|v| v.as_str()), Some("b"));
+    assert_eq!(config.get("missing"), None);
+}
+
+#[test]
+fn round_trip() {
+    let mut lua = Lua::new();
+    let _: () = lua.exec_string("t = { x = 1, y = { 'deep' } } function f() return 42 end").unwrap();
+
+    let t: LuaValue = lua.query("t").unwrap();
+    let f: LuaValue = lua.query("f").unwrap();
+    assert_eq!(f.type_name(), "function");
+    lua.set("t2", t.clone());
+    lua.set("f2", f.clone());
+    assert_eq!(lua.get_top(), 0);
+
+    let t2: LuaValue = lua.query("t2").unwrap();
+    assert_eq!(t2.get("x"), t.get("x"));
+    assert_eq!(t2.get("y"), t.get("y"));
+
+    // functions are the same Lua object, not a copy
+    let same: bool = lua.exec_string("return f == f2").unwrap();
+    assert!(same);
+    let val: i32 = lua.exec_string("return f2()").unwrap();
+    assert_eq!(val, 42);
+    assert_eq!(lua.query::<LuaValue, _>("f2").unwrap(), f);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn cyclic_table() {
+    let mut lua = Lua::new();
+    let _: () = lua.exec_string("t = {} t.self = t").unwrap();
+    let t: Option<LuaValue> = lua.query("t");
+    assert_eq!(lua.get_top(), 0);
+    assert!(t.is_none());
+}
+
+#[test]
+fn nested_tables() {
+    let mut lua = Lua::new();
+    let nested = |depth| format!("local t = {{}} local c = t for i = 1, {} do c[1] = {{}} c = c[1] end return t", depth);
+
+    let t: Option<LuaValue> = lua.exec_string(nested(20));
+    assert_eq!(lua.get_top(), 0);
+    let mut t = t.unwrap();
+    let mut depth = 0;
+    while let LuaValue::Table(mut pairs) = t {
+        t = match pairs.pop() {
+            Some((_, value)) => value,
+            None => break,
+        };
+        depth += 1;
+    }
+    assert_eq!(depth, 20);
+
+    // pushing back a table nested to the limit
+    let t: LuaValue = lua.exec_string(nested(td_rlua::lua_value::MAX_DEPTH - 1)).unwrap();
+    lua.set("t", t);
+    let depth: i32 = lua.exec_string("local n = 0 while t[1] do t = t[1] n = n + 1 end return n").unwrap();
+    assert_eq!(depth, td_rlua::lua_value::MAX_DEPTH as i32 - 1);
+
+    for &depth in &[td_rlua::lua_value::MAX_DEPTH, 100000] {
+        let t: Option<LuaValue> = lua.exec_string(nested(depth));
+        assert_eq!(lua.get_top(), 0);
+        assert!(t.is_none());
+    }
+}
+
+#[test]
+fn invalid_keys() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let t = LuaValue::Table(vec![
+        (LuaValue::Nil, LuaValue::Integer(1)),
+        (LuaValue::Number(::std::f64::NAN), LuaValue::Integer(2)),
+        (LuaValue::Integer(1), LuaValue::Integer(3)),
+    ]);
+    lua.set("t", t);
+    let count: i32 = lua.exec_string("local n = 0 for _ in pairs(t) do n = n + 1 end return n * 10 + t[1]").unwrap();
+    assert_eq!(count, 13);
+    assert_eq!(lua.get_top(), 0);
+}
@@ -0,0 +1,67 @@
+extern crate td_rlua;
+
+use std::alloc::{GlobalAlloc, Layout, System};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use td_rlua::Lua;
+use td_rlua::LuaError;
+
+#[test]
+fn memory_limit() {
+    let limit = 512 * 1024;
+    let mut lua = Lua::with_memory_limit(limit);
+    lua.openlibs();
+    assert!(lua.memory_used() > 0);
+
+    let result: Result<(), LuaError> = lua.try_exec_string("local t = {} for i = 1, 1e7 do t[i] = i end");
+    match result {
+        Err(LuaError::Memory(message)) => assert!(message.contains("not enough memory")),
+        other => panic!("unexpected {:?}", other),
+    }
+    let peak = lua.peak_memory_used().unwrap();
+    assert!(peak <= limit);
+    assert!(peak >= lua.memory_used());
+
+    // the memory is given back once collected
+    let _: () = lua.exec_string("collectgarbage()").unwrap();
+    assert!(lua.memory_used() < limit / 2);
+    let len: i32 = lua.exec_string("local t = {} for i = 1, 1000 do t[i] = i end return #t").unwrap();
+    assert_eq!(len, 1000);
+
+    lua.set_memory_limit(None);
+    let _: () = lua.exec_string("local t = {} for i = 1, 1e5 do t[i] = i end").unwrap();
+    assert!(lua.peak_memory_used().unwrap() > limit);
+    assert_eq!(lua.get_top(), 0);
+}
+
+struct Counting(Arc<AtomicUsize>);
+
+unsafe impl GlobalAlloc for Counting {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        self.0.fetch_add(layout.size(), Ordering::SeqCst);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        self.0.fetch_sub(layout.size(), Ordering::SeqCst);
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[test]
+fn custom_allocator() {
+    let live = Arc::new(AtomicUsize::new(0));
+    {
+        let mut lua = Lua::with_allocator(Counting(live.clone()));
+        lua.openlibs();
+        let _: () = lua.exec_string("t = {} for i = 1, 100 do t[i] = tostring(i) end").unwrap();
+        assert_eq!(live.load(Ordering::SeqCst), lua.memory_used());
+    }
+    assert_eq!(live.load(Ordering::SeqCst), 0);
+
+    // states made by Lua::new only report the memory counted by Lua
+    let lua = Lua::new();
+    assert!(lua.memory_used() > 0);
+    assert_eq!(lua.peak_memory_used(), None);
+}
@@ -0,0 +1,75 @@
+extern crate td_rlua;
+
+use std::panic::{self, AssertUnwindSafe};
+
+use td_rlua::Lua;
+use td_rlua::LuaError;
+
+#[test]
+fn panic_to_error() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let mut calls = 0;
+    lua.set("f", td_rlua::function1(move |fail: bool| {
+        calls += 1;
+        if fail {
+            panic!("boom {}", calls);
+        }
+        calls
+    }));
+
+    match lua.try_exec_string::<_, ()>("f(true)") {
+        Err(LuaError::Runtime { message, .. }) => assert_eq!(message, "rust panic: boom 1"),
+        other => panic!("unexpected {:?}", other),
+    }
+    // the function can be called again after it panicked
+    let calls: i32 = lua.exec_string("return f(false)").unwrap();
+    assert_eq!(calls, 2);
+
+    let message: String = lua.exec_string("local ok, err = pcall(f, true) return err").unwrap();
+    assert_eq!(message, "rust panic: boom 3");
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn resume_panics() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set_resume_panics(true);
+    lua.set("f", td_rlua::function0(|| -> i32 { panic!("boom") }));
+
+    let result = panic::catch_unwind(AssertUnwindSafe(|| lua.exec_string::<_, ()>("f()")));
+    let payload = result.unwrap_err();
+    assert_eq!(payload.downcast_ref::<&str>(), Some(&"boom"));
+    assert_eq!(lua.get_top(), 0);
+
+    // a panic caught by the script isn't resumed by a later error
+    let _: () = lua.exec_string("pcall(f)").unwrap();
+    match lua.try_exec_string::<_, ()>("error('other')") {
+        Err(LuaError::Runtime { message, .. }) => assert!(message.ends_with("other")),
+        other => panic!("unexpected {:?}", other),
+    }
+}
+
+struct PanicOnDrop;
+
+impl Drop for PanicOnDrop {
+    fn drop(&mut self) {
+        panic!("dropped");
+    }
+}
+
+#[test]
+fn panic_in_destructor() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let value = PanicOnDrop;
+    lua.set("f", td_rlua::function0(move || {
+        let _ = &value;
+    }));
+
+    match lua.try_exec_string::<_, ()>("f = nil collectgarbage()") {
+        Err(LuaError::Runtime { message, .. }) => assert!(message.contains("rust panic: dropped")),
+        other => panic!("unexpected {:?}", other),
+    }
+}
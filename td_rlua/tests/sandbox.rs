@@ -0,0 +1,157 @@
+extern crate td_rlua;
+
+use td_rlua::Lua;
+use td_rlua::LuaError;
+use td_rlua::Sandbox;
+use td_rlua::StdLib;
+
+#[test]
+fn selected_libs() {
+    let mut lua = Lua::new_with_libs(StdLib::BASE | StdLib::STRING | StdLib::TABLE);
+    let opened: bool = lua.exec_string("return type(print) == 'function' and string ~= nil and table ~= nil").unwrap();
+    assert!(opened);
+    let missing: bool = lua.exec_string("return io == nil and os == nil and debug == nil and math == nil").unwrap();
+    assert!(missing);
+
+    let mut lua = Lua::new_with_libs(StdLib::NONE);
+    let missing: bool = lua.exec_string("return print == nil and _G == nil").unwrap();
+    assert!(missing);
+    lua.open_libs(StdLib::MATH);
+    let floor: i32 = lua.exec_string("return math.floor(2.5)").unwrap();
+    assert_eq!(floor, 2);
+
+    let libs = StdLib::BASE | StdLib::OS;
+    assert!(libs.contains(StdLib::OS));
+    assert!(!libs.contains(StdLib::OS | StdLib::IO));
+    assert!(StdLib::ALL.contains(StdLib::SAFE));
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn whitelisted_globals() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let env = Sandbox::new().build(&mut lua);
+
+    let len: i32 = lua.try_exec_string_in(&env, "return #string.rep('ab', 2)").unwrap();
+    assert_eq!(len, 4);
+    let time: bool = lua.try_exec_string_in(&env, "return os.time() > 0").unwrap();
+    assert!(time);
+    let hidden: bool = lua.try_exec_string_in(&env, r"
+        return io == nil and os.execute == nil and dofile == nil and loadfile == nil
+            and require == nil and package == nil and debug == nil
+    ").unwrap();
+    assert!(hidden);
+
+    // globals are set in the environment and library tables are copies
+    let _: () = lua.try_exec_string_in(&env, "x = 1 string.upper = nil").unwrap();
+    let x: Option<i32> = lua.query("x");
+    assert_eq!(x, None);
+    assert_eq!(env.query::<i32, _>("x"), Some(1));
+    let upper: String = lua.exec_string("return string.upper('a')").unwrap();
+    assert_eq!(upper, "A");
+    let same_env: bool = lua.try_exec_string_in(&env, "return _G == _ENV").unwrap();
+    assert!(same_env);
+
+    // each environment is independent
+    let other = Sandbox::new().build(&mut lua);
+    let x: Option<i32> = lua.exec_string_in(&other, "return x");
+    assert_eq!(x, None);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn allow_and_deny() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let env = Sandbox::new()
+        .allow("os.getenv")
+        .allow("require")
+        .deny("print")
+        .deny("string.rep")
+        .deny("missing.value")
+        .build(&mut lua);
+    let result: bool = lua.try_exec_string_in(&env, r"
+        return type(os.getenv) == 'function' and print == nil and string.rep == nil
+            and string.sub ~= nil and require == nil and missing == nil
+    ").unwrap();
+    assert!(result);
+
+    let env = Sandbox::empty().allow("tostring").build(&mut lua);
+    let s: String = lua.try_exec_string_in(&env, "return tostring(pairs)").unwrap();
+    assert_eq!(s, "nil");
+
+    lua.set("secret", 42);
+    let result: Result<i32, LuaError> = lua.try_exec_string_in(&env, "return secret + 1");
+    assert!(result.is_err());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn sandboxed_load() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set("secret", 42);
+    let env = Sandbox::new().build(&mut lua);
+
+    let hidden: bool = lua.try_exec_string_in(&env, "return load('return secret')() == nil").unwrap();
+    assert!(hidden);
+    let value: i32 = lua.try_exec_string_in(&env, "return load('return x', 'chunk', 't', { x = 3 })()").unwrap();
+    assert_eq!(value, 3);
+
+    let refused: bool = lua.try_exec_string_in(&env, r"
+        local f, err = load(string.dump(function() return 1 end))
+        return f == nil and err:find('binary') ~= nil
+    ").unwrap();
+    assert!(refused);
+    match lua.try_exec_string_in::<_, ()>(&env, "\x1bLua") {
+        Err(LuaError::Syntax(message)) => assert!(message.contains("binary")),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn string_metatable_hidden() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let env = Sandbox::new().build(&mut lua);
+    let other = Sandbox::new().build(&mut lua);
+
+    let result: Result<(), LuaError> = lua.try_exec_string_in(&env, r"
+        getmetatable('').__index.upper = function() return 'pwned' end
+    ");
+    assert!(result.is_err());
+    let hidden: bool = lua.try_exec_string_in(&env, "return getmetatable('') == nil").unwrap();
+    assert!(hidden);
+    let meta: bool = lua.try_exec_string_in(&env, "return getmetatable(setmetatable({}, { x = 1 })).x == 1").unwrap();
+    assert!(meta);
+
+    let upper: String = lua.try_exec_string_in(&other, "return string.upper('a') .. ('b'):upper()").unwrap();
+    assert_eq!(upper, "AB");
+    let upper: String = lua.exec_string("return string.upper('a')").unwrap();
+    assert_eq!(upper, "A");
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn denied_string_methods() {
+    let mut lua = Lua::new();
+    lua.openlibs();
+    let env = Sandbox::new().deny("string.rep").build(&mut lua);
+    let empty = Sandbox::empty().build(&mut lua);
+
+    let result: Result<String, LuaError> = lua.try_exec_string_in(&env, "return ('x'):rep(3)");
+    assert!(result.is_err());
+    let result: Result<String, LuaError> = lua.try_exec_string_in(&env, "local s = 'x' return s.rep(s, 3)");
+    assert!(result.is_err());
+    let upper: String = lua.try_exec_string_in(&env, "return ('x'):upper()").unwrap();
+    assert_eq!(upper, "X");
+    let result: Result<String, LuaError> = lua.try_exec_string_in(&empty, "return ('x'):upper()");
+    assert!(result.is_err());
+
+    // the methods of the state are restored afterwards
+    let repeated: String = lua.exec_string("return ('x'):rep(3)").unwrap();
+    assert_eq!(repeated, "xxx");
+    assert_eq!(lua.get_top(), 0);
+}
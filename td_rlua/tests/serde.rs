@@ -0,0 +1,171 @@
+#![cfg(feature = "serde")]
+
+extern crate td_rlua;
+#[macro_use]
+extern crate serde_derive;
+extern crate serde_bytes;
+extern crate serde_json;
+
+use std::collections::HashMap;
+
+use td_rlua::Lua;
+use td_rlua::LuaError;
+use td_rlua::LuaValue;
+use td_rlua::Serde;
+use td_rlua::lua_serde::{self, ArrayDetection, SerdeOptions};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+enum Shape {
+    Empty,
+    Circle(f64),
+    Point(i32, i32),
+    Rect { w: u32, h: u32 },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+struct Config {
+    name: String,
+    port: u16,
+    tags: Vec<String>,
+    limits: HashMap<String, i64>,
+    shapes: Vec<Shape>,
+    parent: Option<String>,
+    #[serde(with = "serde_bytes")]
+    key: Vec<u8>,
+}
+
+fn config() -> Config {
+    let mut limits = HashMap::new();
+    limits.insert("conn".to_string(), 100);
+    Config {
+        name: "server".to_string(),
+        port: 8080,
+        tags: vec!["a".to_string(), "b".to_string()],
+        limits,
+        shapes: vec![Shape::Empty, Shape::Circle(1.5), Shape::Point(1, 2), Shape::Rect { w: 3, h: 4 }],
+        parent: None,
+        key: vec![0, 255, 1],
+    }
+}
+
+#[test]
+fn round_trip() {
+    let mut lua = Lua::new();
+    lua.set("config", Serde(config()));
+
+    let name: String = lua.exec_string("return config.name").unwrap();
+    assert_eq!(name, "server");
+    let tag: String = lua.exec_string("return config.tags[2]").unwrap();
+    assert_eq!(tag, "b");
+    let empty: String = lua.exec_string("return config.shapes[1]").unwrap();
+    assert_eq!(empty, "Empty");
+    let w: u32 = lua.exec_string("return config.shapes[4].Rect.w").unwrap();
+    assert_eq!(w, 3);
+    let has_parent: bool = lua.exec_string("return config.parent ~= nil").unwrap();
+    assert!(!has_parent);
+
+    let Serde(read): Serde<Config> = lua.query("config").unwrap();
+    assert_eq!(read, config());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn read_from_script() {
+    let mut lua = Lua::new();
+    let _: () = lua.exec_string(r"
+        config = {
+            name = 'script', port = 1.0, tags = {},
+            limits = { [1] = 10, mem = 20 },
+            shapes = { { Circle = 2 }, { Point = { 5, 6 } } },
+            parent = 'root', key = 'k',
+        }
+    ").unwrap();
+    let Serde(read): Serde<Config> = lua.query("config").unwrap();
+    assert_eq!(read.port, 1);
+    assert_eq!(read.limits["1"], 10);
+    assert_eq!(read.shapes, vec![Shape::Circle(2.0), Shape::Point(5, 6)]);
+    assert_eq!(read.parent, Some("root".to_string()));
+    assert_eq!(read.key, b"k".to_vec());
+}
+
+#[test]
+fn conversion_errors() {
+    let mut lua = Lua::new();
+    let t: LuaValue = lua.exec_string("return { 1, 2, 'x' }").unwrap();
+    let result: Result<Vec<i32>, LuaError> = lua_serde::from_value(t.clone());
+    match result {
+        Err(LuaError::Conversion { ref expected, ref actual }) => {
+            assert_eq!(expected, "i32");
+            assert_eq!(actual, "string \"x\"");
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    let result: Result<(i32, i32, String), LuaError> = lua_serde::from_value(t.clone());
+    assert!(result.is_ok());
+    let result: Result<(i32, i32), LuaError> = lua_serde::from_value(t.clone());
+    assert!(result.is_err());
+
+    lua.set("t", t);
+
+    let missing: Option<Serde<Config>> = lua.query("t");
+    assert!(missing.is_none());
+    assert_eq!(lua.get_top(), 0);
+
+    let _: () = lua.exec_string("deep = {} local c = deep for i = 1, 100000 do c[1] = {} c = c[1] end").unwrap();
+    let deep: Option<Serde<serde_json::Value>> = lua.query("deep");
+    assert!(deep.is_none());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn none_as_null() {
+    let mut lua = Lua::new();
+    let values = vec![Some(1), None, Some(3)];
+    let options = SerdeOptions { none_as_null: true, ..SerdeOptions::default() };
+    lua.set("t", lua_serde::to_value_with_options(&values, options).unwrap());
+    lua.set("null", lua_serde::Null);
+
+    let len: i32 = lua.exec_string("return #t").unwrap();
+    assert_eq!(len, 3);
+    let is_null: bool = lua.exec_string("return t[2] == null").unwrap();
+    assert!(is_null);
+    let Serde(read): Serde<Vec<Option<i32>>> = lua.query("t").unwrap();
+    assert_eq!(read, values);
+
+    // without the option the None leaves a hole
+    lua.set("t", Serde(values.clone()));
+    let is_nil: bool = lua.exec_string("return t[2] == nil").unwrap();
+    assert!(is_nil);
+    let Serde(read): Serde<Vec<Option<i32>>> = lua.query("t").unwrap();
+    assert_eq!(read, values);
+}
+
+#[test]
+fn array_detection() {
+    let mut lua = Lua::new();
+    let _: () = lua.exec_string("seq = { 1, 2 } holes = { [1] = 1, [3] = 3 } empty = {}").unwrap();
+
+    let Serde(seq): Serde<serde_json::Value> = lua.query("seq").unwrap();
+    assert_eq!(seq, serde_json::json!([1, 2]));
+    let Serde(holes): Serde<serde_json::Value> = lua.query("holes").unwrap();
+    assert_eq!(holes, serde_json::json!({ "1": 1, "3": 3 }));
+    let Serde(empty): Serde<serde_json::Value> = lua.query("empty").unwrap();
+    assert_eq!(empty, serde_json::json!({}));
+
+    let options = SerdeOptions {
+        array_detection: ArrayDetection::PositiveIntegers,
+        empty_table_as_array: true,
+        ..SerdeOptions::default()
+    };
+    let read = |lua: &mut Lua, name: &str| -> serde_json::Value {
+        let value: LuaValue = lua.query(name).unwrap();
+        lua_serde::from_value_with_options(value, options).unwrap()
+    };
+    assert_eq!(read(&mut lua, "holes"), serde_json::json!([1, null, 3]));
+    assert_eq!(read(&mut lua, "empty"), serde_json::json!([]));
+
+    let options = SerdeOptions { array_detection: ArrayDetection::Never, ..SerdeOptions::default() };
+    let value: LuaValue = lua.query("seq").unwrap();
+    let seq: serde_json::Value = lua_serde::from_value_with_options(value, options).unwrap();
+    assert_eq!(seq, serde_json::json!({ "1": 1, "2": 2 }));
+}
@@ -225,4 +225,430 @@ fn custom_struct() {
 
     let _obj : Option<&mut TestLuaSturct> = lua.query("x1");
     assert_eq!(lua.get_top(), 0);
-}
\ No newline at end of file
+}
+#[test]
+fn properties() {
+    struct Entity {
+        hp: i32,
+        name: String,
+    }
+
+    impl NewStruct for Entity {
+        fn new() -> Entity {
+            Entity { hp: 100, name: "orc".to_string() }
+        }
+
+        fn name() -> &'static str {
+            "Entity"
+        }
+    }
+
+    impl<'a> LuaRead for &'a mut Entity {
+        fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut Entity> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    td_rlua::LuaStruct::<Entity>::new(lua.state())
+        .create()
+        .def("heal", td_rlua::function2(|this: &mut Entity, amount: i32| this.hp += amount))
+        .field_getter("hp", td_rlua::function1(|this: &mut Entity| this.hp))
+        .field_setter("hp", td_rlua::function2(|this: &mut Entity, hp: i32| this.hp = hp))
+        .field_getter("name", td_rlua::function1(|this: &mut Entity| this.name.clone()));
+
+    let hp: i32 = lua.exec_string("e = Entity() e.hp = 10 e:heal(5) return e.hp").unwrap();
+    assert_eq!(hp, 15);
+    let name: String = lua.exec_string("return e.name").unwrap();
+    assert_eq!(name, "orc");
+    let missing: bool = lua.exec_string("return e.unknown == nil").unwrap();
+    assert!(missing);
+
+    match lua.try_exec_string::<_, ()>("e.name = 'elf'") {
+        Err(td_rlua::LuaError::Runtime { message, .. }) => {
+            assert!(message.ends_with("cannot set unknown field 'name' of Entity"))
+        }
+        other => panic!("unexpected {:?}", other),
+    }
+    match lua.try_exec_string::<_, ()>("e.hp = 'full'") {
+        Err(td_rlua::LuaError::Runtime { message, .. }) => assert!(message.contains("number expected, got string")),
+        other => panic!("unexpected {:?}", other),
+    }
+    let entity: &mut Entity = lua.query("e").unwrap();
+    assert_eq!(entity.hp, 15);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn operators() {
+    use td_rlua::MetaMethod;
+
+    #[derive(Debug, PartialEq)]
+    struct Vec2 {
+        x: f64,
+        y: f64,
+    }
+
+    impl NewStruct for Vec2 {
+        fn new() -> Vec2 {
+            Vec2 { x: 0.0, y: 0.0 }
+        }
+
+        fn name() -> &'static str {
+            "Vec2"
+        }
+    }
+
+    impl LuaPush for Vec2 {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            unsafe { td_rlua::push_struct(self, lua) }
+        }
+    }
+
+    impl<'a> LuaRead for &'a Vec2 {
+        fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a Vec2> {
+            unsafe { td_rlua::userdata::read_userdata_ref(lua, index) }
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    td_rlua::LuaStruct::<Vec2>::new(lua.state())
+        .create()
+        .def("dot", td_rlua::function2(|a: &Vec2, b: &Vec2| a.x * b.x + a.y * b.y))
+        .meta(MetaMethod::Add, td_rlua::function2(|a: &Vec2, b: &Vec2| Vec2 { x: a.x + b.x, y: a.y + b.y }))
+        .meta(MetaMethod::Mul, td_rlua::function2(|a: &Vec2, k: f64| Vec2 { x: a.x * k, y: a.y * k }))
+        .meta(MetaMethod::Unm, td_rlua::function1(|a: &Vec2| Vec2 { x: -a.x, y: -a.y }))
+        .meta(MetaMethod::Eq, td_rlua::function2(|a: &Vec2, b: &Vec2| a.x == b.x && a.y == b.y))
+        .meta(MetaMethod::Lt, td_rlua::function2(|a: &Vec2, b: &Vec2| a.x < b.x))
+        .meta(MetaMethod::Len, td_rlua::function1(|a: &Vec2| (a.x * a.x + a.y * a.y).sqrt()))
+        .meta(MetaMethod::ToString, td_rlua::function1(|a: &Vec2| format!("({}, {})", a.x, a.y)))
+        .meta(MetaMethod::Concat, td_rlua::function2(|a: String, b: &Vec2| format!("{}({}, {})", a, b.x, b.y)))
+        .meta(MetaMethod::Call, td_rlua::function2(|a: &Vec2, i: i32| if i == 1 { a.x } else { a.y }));
+    lua.set("a", Vec2 { x: 3.0, y: 4.0 });
+    lua.set("b", Vec2 { x: 1.0, y: 2.0 });
+
+    let sum: &Vec2 = lua.exec_string("return -(a + b * 2)").unwrap();
+    assert_eq!(*sum, Vec2 { x: -5.0, y: -8.0 });
+    // both operands are the same object
+    let double: &Vec2 = lua.exec_string("return a + a").unwrap();
+    assert_eq!(*double, Vec2 { x: 6.0, y: 8.0 });
+    let same: bool = lua.exec_string("local c = a + b return c:dot(c) == 52 and c == c").unwrap();
+    assert!(same);
+    let checks: String = lua.exec_string(r#"
+        return table.concat({ tostring(#a), tostring(a + b), "v" .. b, tostring(a(2)),
+                              tostring(a == b), tostring(a + b == Vec2() + a + b), tostring(b < a),
+                              tostring((a + b):dot(b)) }, " ")
+    "#).unwrap();
+    assert_eq!(checks, "5.0 (4, 6) v(1, 2) 4.0 false true true 16.0");
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn constructors() {
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        x: f64,
+        y: f64,
+    }
+
+    impl NewStruct for Point {
+        fn new() -> Point {
+            Point { x: 0.0, y: 0.0 }
+        }
+
+        fn name() -> &'static str {
+            "Point"
+        }
+    }
+
+    impl LuaPush for Point {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            unsafe { td_rlua::push_struct(self, lua) }
+        }
+    }
+
+    impl<'a> LuaRead for &'a mut Point {
+        fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut Point> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    td_rlua::LuaStruct::<Point>::new(lua.state())
+        .constructor(td_rlua::function2(|x: f64, y: f64| Point { x, y }))
+        .constructor(td_rlua::function1(|x: f64| Point { x, y: x }))
+        .create()
+        .def("sum", td_rlua::function1(|p: &mut Point| p.x + p.y));
+
+    let p: &mut Point = lua.exec_string("return Point(1, 2)").unwrap();
+    assert_eq!(*p, Point { x: 1.0, y: 2.0 });
+    let sum: f64 = lua.exec_string("return Point(3):sum() + Point():sum()").unwrap();
+    assert_eq!(sum, 6.0);
+
+    match lua.try_exec_string::<_, ()>("Point(1, 2, 3)") {
+        Err(td_rlua::LuaError::Runtime { message, .. }) => assert_eq!(message, "no constructor of Point takes 3 arguments"),
+        other => panic!("unexpected {:?}", other),
+    }
+    match lua.try_exec_string::<_, ()>("Point(1, 'x')") {
+        Err(td_rlua::LuaError::Runtime { message, .. }) => assert!(message.contains("#2")),
+        other => panic!("unexpected {:?}", other),
+    }
+    assert_eq!(lua.get_top(), 0);
+
+    // the objects made by a constructor get the metatable whatever their `LuaPush`
+    struct Size(i32);
+
+    impl NewStruct for Size {
+        fn new() -> Size {
+            Size(0)
+        }
+
+        fn name() -> &'static str {
+            "Size"
+        }
+    }
+
+    impl LuaPush for Size {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::userdata::push_userdata(self, lua, |_| {})
+        }
+    }
+
+    impl<'a> LuaRead for &'a mut Size {
+        fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut Size> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    td_rlua::LuaStruct::<Size>::new(lua.state())
+        .constructor(td_rlua::function1(|size: i32| Size(size)))
+        .create()
+        .def("double", td_rlua::function1(|s: &mut Size| s.0 * 2));
+    let double: i32 = lua.exec_string("return Size(21):double()").unwrap();
+    assert_eq!(double, 42);
+    let size: &mut Size = lua.exec_string("return Size(3)").unwrap();
+    assert_eq!(size.0, 3);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn registry_metatables() {
+    use td_rlua::LuaStruct;
+
+    mod first {
+        pub struct Item(pub i32);
+    }
+    mod second {
+        pub struct Item(pub String);
+    }
+
+    impl NewStruct for first::Item {
+        fn new() -> first::Item {
+            first::Item(1)
+        }
+
+        fn name() -> &'static str {
+            "Item"
+        }
+    }
+
+    impl NewStruct for second::Item {
+        fn new() -> second::Item {
+            second::Item("one".to_string())
+        }
+
+        fn name() -> &'static str {
+            "Item"
+        }
+    }
+
+    impl LuaPush for first::Item {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            unsafe { td_rlua::push_struct(self, lua) }
+        }
+    }
+
+    impl<'a> LuaRead for &'a mut first::Item {
+        fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut first::Item> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    impl<'a> LuaRead for &'a mut second::Item {
+        fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut second::Item> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    assert!(!LuaStruct::<first::Item>::is_registered(lua.state()));
+
+    // without `create` no global is defined
+    LuaStruct::<first::Item>::new(lua.state())
+        .def("get", td_rlua::function1(|item: &mut first::Item| item.0));
+    assert!(LuaStruct::<first::Item>::is_registered(lua.state()));
+    assert!(!LuaStruct::<second::Item>::is_registered(lua.state()));
+    let global: bool = lua.exec_string("return Item == nil").unwrap();
+    assert!(global);
+    lua.set("a", first::Item(5));
+
+    // a type with the same name has its own metatable
+    LuaStruct::<second::Item>::new(lua.state())
+        .create()
+        .def("get", td_rlua::function1(|item: &mut second::Item| item.0.clone()));
+    let values: String = lua.exec_string("b = Item() return a:get() .. b:get()").unwrap();
+    assert_eq!(values, "5one");
+
+    // replacing the global only removes the constructor
+    let _: () = lua.exec_string("Item = nil").unwrap();
+    let values: String = lua.exec_string("return a:get() .. b:get()").unwrap();
+    assert_eq!(values, "5one");
+    let name: bool = lua.exec_string("return tostring(b):find('^Item: ') ~= nil").unwrap();
+    assert!(name);
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn shared_metatables() {
+    use std::cell::Cell;
+
+    struct Foo;
+    struct Bar;
+    thread_local!(static FILLED: Cell<i32> = Cell::new(0));
+    impl LuaPush for Foo {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::userdata::push_userdata(self, lua, |_| FILLED.with(|f| f.set(f.get() + 1)))
+        }
+    }
+    impl LuaPush for Bar {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::userdata::push_userdata(self, lua, |_| {})
+        }
+    }
+    impl<'a> LuaRead for &'a mut Foo {
+        fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut Foo> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    lua.set("a", Foo);
+    lua.set("b", Foo);
+    lua.set("c", Bar);
+    assert_eq!(FILLED.with(|f| f.get()), 1);
+
+    let shared: bool = lua.exec_string("return getmetatable(a) == getmetatable(b)").unwrap();
+    assert!(shared);
+    let shared: bool = lua.exec_string("return getmetatable(a) == getmetatable(c)").unwrap();
+    assert!(!shared);
+    assert!(lua.query::<&mut Foo, _>("b").is_some());
+    assert!(lua.query::<&mut Foo, _>("c").is_none());
+
+    // a metatable given by the script isn't taken for the one of the type
+    let _: () = lua.exec_string("d = io.stdout").unwrap();
+    assert!(lua.query::<&mut Foo, _>("d").is_none());
+
+    // all the light user data share one metatable, so they can't be read as a type
+    struct Light<'a>(&'a mut Foo);
+    impl<'a> LuaPush for Light<'a> {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            td_rlua::userdata::push_lightuserdata(self.0, lua, |_| {})
+        }
+    }
+    let mut foo = Foo;
+    lua.set("e", Light(&mut foo));
+    assert!(lua.query::<&mut Foo, _>("e").is_none());
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn light_structs() {
+    struct Counter(i32);
+
+    impl NewStruct for Counter {
+        fn new() -> Counter {
+            Counter(1)
+        }
+
+        fn name() -> &'static str {
+            "Counter"
+        }
+    }
+
+    impl<'a> LuaRead for &'a mut Counter {
+        fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut Counter> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    let mut lua = Lua::new();
+    td_rlua::LuaStruct::<Counter>::new_light(lua.state())
+        .create()
+        .def("incr", td_rlua::function1(|c: &mut Counter| { c.0 += 1; c.0 }));
+    let count: i32 = lua.exec_string("c = Counter() c:incr() return c:incr()").unwrap();
+    assert_eq!(count, 3);
+    let counter: &mut Counter = lua.query("c").unwrap();
+    assert_eq!(counter.0, 3);
+    // the light object is owned by Rust
+    unsafe { drop(Box::from_raw(counter as *mut Counter)) };
+    assert_eq!(lua.get_top(), 0);
+}
+
+#[test]
+fn light_struct_drops() {
+    use std::cell::Cell;
+
+    thread_local!(static DROPPED: Cell<i32> = Cell::new(0));
+    struct Token;
+
+    impl NewStruct for Token {
+        fn new() -> Token {
+            Token
+        }
+
+        fn name() -> &'static str {
+            "Token"
+        }
+    }
+
+    impl Drop for Token {
+        fn drop(&mut self) {
+            DROPPED.with(|d| d.set(d.get() + 1));
+        }
+    }
+
+    impl LuaPush for Token {
+        fn push_to_lua(self, lua: *mut lua_State) -> i32 {
+            unsafe { td_rlua::push_struct(self, lua) }
+        }
+    }
+
+    impl<'a> LuaRead for &'a mut Token {
+        fn lua_read_with_pop_impl(lua: *mut lua_State, index: i32, _pop: i32) -> Option<&'a mut Token> {
+            td_rlua::userdata::read_userdata(lua, index)
+        }
+    }
+
+    let mut lua = Lua::new();
+    lua.openlibs();
+    td_rlua::LuaStruct::<Token>::new_light(lua.state())
+        .create()
+        .constructor(td_rlua::function1(|_: i32| Token));
+    // the light object pushed first doesn't keep the others from being dropped
+    let _: () = lua.exec_string("light = Token()").unwrap();
+    let light: &mut Token = lua.query("light").unwrap();
+    lua.set("pushed", Token);
+    let _: () = lua.exec_string("constructed = Token(1)").unwrap();
+    assert_eq!(DROPPED.with(|d| d.get()), 0);
+
+    let _: () = lua.exec_string("pushed = nil constructed = nil collectgarbage()").unwrap();
+    assert_eq!(DROPPED.with(|d| d.get()), 2);
+    unsafe { drop(Box::from_raw(light as *mut Token)) };
+    assert_eq!(DROPPED.with(|d| d.get()), 3);
+    assert_eq!(lua.get_top(), 0);
+}
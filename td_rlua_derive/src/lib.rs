@@ -0,0 +1,367 @@
+//! Derive macros for td_rlua, re-exported by td_rlua when its `derive` feature is enabled.
+//!
+//! - `#[derive(LuaPush, LuaRead)]` converts a struct to and from a Lua table, one entry per
+//!   field, or to and from a user data with `#[lua(userdata)]`.
+//! - `#[lua_methods]` on an `impl` block implements `LuaMethods`, so that
+//!   `LuaStruct::def_methods` registers all its methods at once.
+
+extern crate proc_macro;
+extern crate proc_macro2;
+#[macro_use]
+extern crate quote;
+extern crate syn;
+
+use proc_macro::TokenStream;
+use proc_macro2::{Literal, Span, TokenStream as TokenStream2};
+use syn::{Attribute, Data, DeriveInput, Fields, FnArg, Ident, ImplItem, ItemImpl, LitStr, Member};
+use syn::spanned::Spanned;
+
+/// Implements `LuaPush` for the struct.
+///
+/// By default the struct is pushed as a new table holding its fields, the keys being the
+/// names of the fields, or `1..n` for a tuple struct. With `#[lua(userdata)]` the struct is
+/// moved into a user data instead, given the metatable of its `LuaStruct` with `push_struct`
+/// when it implements `NewStruct`.
+///
+/// Field attributes: `#[lua(rename = "name")]` changes the key of the field and `#[lua(skip)]`
+/// leaves the field out.
+#[proc_macro_derive(LuaPush, attributes(lua))]
+pub fn derive_lua_push(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    expand_push(&input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+/// Implements `LuaRead` for the struct, the counterpart of `#[derive(LuaPush)]`.
+///
+/// A table is read field by field, skipped fields being set to their `Default` value.
+/// With `#[lua(userdata)]`, `LuaRead` is implemented for `&mut` and `&` the struct instead.
+#[proc_macro_derive(LuaRead, attributes(lua))]
+pub fn derive_lua_read(input: TokenStream) -> TokenStream {
+    let input = syn::parse_macro_input!(input as DeriveInput);
+    expand_read(&input).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+/// Implements `LuaMethods` for the type of the `impl` block.
+///
+/// Every method taking `&self` or `&mut self` is registered under its name, or the one given
+/// with `#[lua(name = "...")]`, `#[lua(skip)]` leaves a method out. Associated functions without
+/// `self` aren't registered.
+///
+/// A method marked `#[lua(getter)]` is registered as `get_<property>` and one marked
+/// `#[lua(setter)]` as `set_<property>`, the property being the name of the method without its
+/// `get_` or `set_` prefix, unless given with `#[lua(getter = "property")]`. The property can
+/// also be read or assigned as a field, `obj.property`.
+#[proc_macro_attribute]
+pub fn lua_methods(args: TokenStream, input: TokenStream) -> TokenStream {
+    if !args.is_empty() {
+        let err = syn::Error::new(Span::call_site(), "#[lua_methods] takes no arguments");
+        return err.to_compile_error().into();
+    }
+    let item = syn::parse_macro_input!(input as ItemImpl);
+    expand_methods(item).unwrap_or_else(|err| err.to_compile_error()).into()
+}
+
+#[derive(Default)]
+struct ContainerAttrs {
+    userdata: bool,
+}
+
+#[derive(Default)]
+struct FieldAttrs {
+    rename: Option<String>,
+    skip: bool,
+}
+
+#[derive(Default)]
+struct MethodAttrs {
+    name: Option<String>,
+    skip: bool,
+    getter: Option<Option<String>>,
+    setter: Option<Option<String>>,
+}
+
+fn is_lua_attr(attr: &Attribute) -> bool {
+    attr.path().is_ident("lua")
+}
+
+fn container_attrs(attrs: &[Attribute]) -> syn::Result<ContainerAttrs> {
+    let mut result = ContainerAttrs::default();
+    for attr in attrs.iter().filter(|attr| is_lua_attr(attr)) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("userdata") {
+                result.userdata = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown lua attribute, expected `userdata`"))
+            }
+        })?;
+    }
+    Ok(result)
+}
+
+fn field_attrs(attrs: &[Attribute]) -> syn::Result<FieldAttrs> {
+    let mut result = FieldAttrs::default();
+    for attr in attrs.iter().filter(|attr| is_lua_attr(attr)) {
+        attr.parse_nested_meta(|meta| {
+            if meta.path.is_ident("rename") {
+                let name: LitStr = meta.value()?.parse()?;
+                result.rename = Some(name.value());
+                Ok(())
+            } else if meta.path.is_ident("skip") {
+                result.skip = true;
+                Ok(())
+            } else {
+                Err(meta.error("unknown lua attribute, expected `rename` or `skip`"))
+            }
+        })?;
+    }
+    Ok(result)
+}
+
+fn method_attrs(attrs: &[Attribute]) -> syn::Result<MethodAttrs> {
+    let mut result = MethodAttrs::default();
+    for attr in attrs.iter().filter(|attr| is_lua_attr(attr)) {
+        attr.parse_nested_meta(|meta| {
+            let optional_name = || -> syn::Result<Option<String>> {
+                if meta.input.peek(syn::Token![=]) {
+                    let name: LitStr = meta.value()?.parse()?;
+                    Ok(Some(name.value()))
+                } else {
+                    Ok(None)
+                }
+            };
+            if meta.path.is_ident("name") {
+                let name: LitStr = meta.value()?.parse()?;
+                result.name = Some(name.value());
+            } else if meta.path.is_ident("skip") {
+                result.skip = true;
+            } else if meta.path.is_ident("getter") {
+                result.getter = Some(optional_name()?);
+            } else if meta.path.is_ident("setter") {
+                result.setter = Some(optional_name()?);
+            } else {
+                return Err(meta.error("unknown lua attribute, expected `name`, `skip`, `getter` or `setter`"));
+            }
+            Ok(())
+        })?;
+    }
+    Ok(result)
+}
+
+/// The fields stored in the table, with their key.
+fn table_fields(input: &DeriveInput) -> syn::Result<Vec<(Member, TokenStream2, FieldAttrs)>> {
+    let fields = match input.data {
+        Data::Struct(ref data) => &data.fields,
+        _ => return Err(syn::Error::new(input.ident.span(),
+                                        "only structs can be converted to a table, use #[lua(userdata)]")),
+    };
+    if let Fields::Unit = *fields {
+        return Err(syn::Error::new(input.ident.span(),
+                                   "unit structs can't be converted to a table, use #[lua(userdata)]"));
+    }
+    let mut result = Vec::new();
+    for (i, field) in fields.iter().enumerate() {
+        let attrs = field_attrs(&field.attrs)?;
+        let (member, key) = match field.ident {
+            Some(ref ident) => {
+                let name = attrs.rename.clone().unwrap_or_else(|| ident.to_string());
+                (Member::Named(ident.clone()), quote!(#name))
+            }
+            None => {
+                let key = match attrs.rename {
+                    Some(ref name) => quote!(#name),
+                    None => {
+                        let key = Literal::i64_unsuffixed(i as i64 + 1);
+                        quote!(#key)
+                    }
+                };
+                (Member::Unnamed(syn::Index::from(i)), key)
+            }
+        };
+        result.push((member, key, attrs));
+    }
+    Ok(result)
+}
+
+fn expand_push(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let body = if container_attrs(&input.attrs)?.userdata {
+        quote! {
+            #[allow(unused_imports)]
+            use ::td_rlua::userdata::{PushStruct, PushUserdata};
+            unsafe { (&::td_rlua::userdata::PushProbe::<Self>::new()).push_value(self, lua) }
+        }
+    } else {
+        let fields = table_fields(input)?;
+        let sets = fields.iter().filter(|field| !field.2.skip).map(|(member, key, _)| {
+            quote! {
+                ::td_rlua::LuaPush::push_to_lua(#key, lua);
+                ::td_rlua::LuaPush::push_to_lua(self.#member, lua);
+                unsafe { ::td_rlua::lua_settable(lua, -3); }
+            }
+        });
+        quote! {
+            unsafe { ::td_rlua::lua_newtable(lua); }
+            #(#sets)*
+            1
+        }
+    };
+    Ok(quote! {
+        impl #impl_generics ::td_rlua::LuaPush for #name #ty_generics #where_clause {
+            fn push_to_lua(self, lua: *mut ::td_rlua::lua_State) -> i32 {
+                #body
+            }
+        }
+    })
+}
+
+fn expand_read(input: &DeriveInput) -> syn::Result<TokenStream2> {
+    let name = &input.ident;
+    if container_attrs(&input.attrs)?.userdata {
+        let mut generics = input.generics.clone();
+        generics.params.insert(0, syn::parse_quote!('__lua));
+        let (impl_generics, _, _) = generics.split_for_impl();
+        let (_, ty_generics, where_clause) = input.generics.split_for_impl();
+        return Ok(quote! {
+            impl #impl_generics ::td_rlua::LuaRead for &'__lua mut #name #ty_generics #where_clause {
+                fn lua_read_with_pop_impl(lua: *mut ::td_rlua::lua_State, index: i32, _pop: i32)
+                    -> Option<&'__lua mut #name #ty_generics>
+                {
+                    ::td_rlua::userdata::read_userdata(lua, index)
+                }
+            }
+
+            impl #impl_generics ::td_rlua::LuaRead for &'__lua #name #ty_generics #where_clause {
+                fn lua_read_with_pop_impl(lua: *mut ::td_rlua::lua_State, index: i32, _pop: i32)
+                    -> Option<&'__lua #name #ty_generics>
+                {
+                    unsafe { ::td_rlua::userdata::read_userdata_ref(lua, index) }
+                }
+            }
+        });
+    }
+
+    let (impl_generics, ty_generics, where_clause) = input.generics.split_for_impl();
+    let fields = table_fields(input)?;
+    let reads = fields.iter().map(|(member, key, attrs)| {
+        if attrs.skip {
+            quote!(#member: ::std::default::Default::default())
+        } else {
+            quote! {
+                #member: {
+                    ::td_rlua::LuaPush::push_to_lua(#key, lua);
+                    unsafe { ::td_rlua::lua_gettable(lua, index); }
+                    ::td_rlua::LuaRead::lua_read_with_pop(lua, -1, 1)?
+                }
+            }
+        }
+    });
+    Ok(quote! {
+        impl #impl_generics ::td_rlua::LuaRead for #name #ty_generics #where_clause {
+            fn lua_read_with_pop_impl(lua: *mut ::td_rlua::lua_State, index: i32, _pop: i32)
+                -> Option<#name #ty_generics>
+            {
+                if !unsafe { ::td_rlua::lua_istable(lua, index) } {
+                    return None;
+                }
+                let index = unsafe { ::td_rlua::lua_absindex(lua, index) };
+                Some(#name {
+                    #(#reads,)*
+                })
+            }
+        }
+    })
+}
+
+fn expand_methods(mut item: ItemImpl) -> syn::Result<TokenStream2> {
+    if let Some((_, ref path, _)) = item.trait_ {
+        return Err(syn::Error::new(path.span(), "#[lua_methods] must be used on an inherent impl block"));
+    }
+    let self_ty = item.self_ty.clone();
+    let mut defs = Vec::new();
+    for impl_item in item.items.iter_mut() {
+        let method = match *impl_item {
+            ImplItem::Fn(ref mut method) => method,
+            _ => continue,
+        };
+        let attrs = method_attrs(&method.attrs)?;
+        method.attrs.retain(|attr| !is_lua_attr(attr));
+        if attrs.skip {
+            continue;
+        }
+        let sig = &method.sig;
+        let receiver = match sig.receiver() {
+            Some(receiver) => receiver,
+            None => continue,
+        };
+        if receiver.reference.is_none() {
+            return Err(syn::Error::new(receiver.span(),
+                                       "methods taking `self` by value can't be registered, add #[lua(skip)]"));
+        }
+        if !sig.generics.params.is_empty() {
+            return Err(syn::Error::new(sig.generics.span(),
+                                       "generic methods can't be registered, add #[lua(skip)]"));
+        }
+
+        let ident = &sig.ident;
+        let mut args = Vec::new();
+        let mut types = Vec::new();
+        for (i, input) in sig.inputs.iter().skip(1).enumerate() {
+            if let FnArg::Typed(ref arg) = *input {
+                args.push(Ident::new(&format!("arg{}", i), Span::call_site()));
+                types.push(arg.ty.clone());
+            }
+        }
+        if args.len() > 9 {
+            return Err(syn::Error::new(sig.inputs.span(), "methods can take at most 9 arguments"));
+        }
+
+        let (lua_name, accessor) = match (attrs.getter, attrs.setter) {
+            (Some(_), Some(_)) => {
+                return Err(syn::Error::new(ident.span(), "a method can't be both a getter and a setter"));
+            }
+            (Some(property), None) => {
+                if !args.is_empty() {
+                    return Err(syn::Error::new(ident.span(), "a getter takes no argument"));
+                }
+                let property = property.unwrap_or_else(|| ident.to_string().trim_start_matches("get_").to_string());
+                (format!("get_{}", property), Some((quote!(field_getter), property)))
+            }
+            (None, Some(property)) => {
+                if args.len() != 1 {
+                    return Err(syn::Error::new(ident.span(), "a setter takes exactly one argument"));
+                }
+                let property = property.unwrap_or_else(|| ident.to_string().trim_start_matches("set_").to_string());
+                (format!("set_{}", property), Some((quote!(field_setter), property)))
+            }
+            (None, None) => (attrs.name.unwrap_or_else(|| ident.to_string()), None),
+        };
+
+        let function = Ident::new(&format!("function{}", args.len() + 1), Span::call_site());
+        let call = quote! {
+            ::td_rlua::#function(|this: &mut #self_ty, #(#args: #types),*| {
+                this.#ident(#(#args),*)
+            })
+        };
+        defs.push(quote! {
+            lua_struct.def(#lua_name, #call);
+        });
+        if let Some((register, property)) = accessor {
+            defs.push(quote! {
+                lua_struct.#register(#property, #call);
+            });
+        }
+    }
+
+    let (impl_generics, _, where_clause) = item.generics.split_for_impl();
+    Ok(quote! {
+        #item
+
+        impl #impl_generics ::td_rlua::LuaMethods for #self_ty #where_clause {
+            fn lua_methods(lua_struct: &mut ::td_rlua::LuaStruct<Self>) {
+                #(#defs)*
+            }
+        }
+    })
+}